@@ -38,10 +38,10 @@ fn main() {
         },
     )
     .unwrap();
-    sock.send_to(&raw.pack().unwrap(), &target).unwrap();
+    sock.send_to(&raw.pack().unwrap(), target).unwrap();
 
     let raw = RawMessage::build(&opts, msg).unwrap();
-    sock.send_to(&raw.pack().unwrap(), &target).unwrap();
+    sock.send_to(&raw.pack().unwrap(), target).unwrap();
 
     let duration = 50;
 
@@ -62,7 +62,7 @@ fn main() {
             };
 
             let raw = RawMessage::build(&opts, msg).unwrap();
-            sock.send_to(&raw.pack().unwrap(), &target).unwrap();
+            sock.send_to(&raw.pack().unwrap(), target).unwrap();
 
             if idx > 0 {
                 let msg = Message::SetColorZones {
@@ -79,7 +79,7 @@ fn main() {
                 };
 
                 let raw = RawMessage::build(&opts, msg).unwrap();
-                sock.send_to(&raw.pack().unwrap(), &target).unwrap();
+                sock.send_to(&raw.pack().unwrap(), target).unwrap();
             }
 
             sleep(Duration::from_millis(duration as u64));
@@ -103,7 +103,7 @@ fn main() {
             };
 
             let raw = RawMessage::build(&opts, msg).unwrap();
-            sock.send_to(&raw.pack().unwrap(), &target).unwrap();
+            sock.send_to(&raw.pack().unwrap(), target).unwrap();
 
             if idx < 15 {
                 let msg = Message::SetColorZones {
@@ -120,7 +120,7 @@ fn main() {
                 };
 
                 let raw = RawMessage::build(&opts, msg).unwrap();
-                sock.send_to(&raw.pack().unwrap(), &target).unwrap();
+                sock.send_to(&raw.pack().unwrap(), target).unwrap();
             }
 
             sleep(Duration::from_millis(duration as u64));
@@ -40,7 +40,7 @@ fn main() {
 
     let raw = RawMessage::build(&opts, msg).unwrap();
     let bytes = raw.pack().unwrap();
-    sock.send_to(&bytes, &target).unwrap();
+    sock.send_to(&bytes, target).unwrap();
 
     let stdin = std::io::stdin();
     let mut s = String::new();
@@ -70,5 +70,5 @@ fn main() {
 
     let raw = RawMessage::build(&opts, msg).unwrap();
     let bytes = raw.pack().unwrap();
-    sock.send_to(&bytes, &target).unwrap();
+    sock.send_to(&bytes, target).unwrap();
 }
@@ -33,7 +33,7 @@ use thiserror::Error;
 
 #[cfg(fuzzing)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ComparableFloat(f32);
 #[cfg(fuzzing)]
 impl PartialEq for ComparableFloat {
@@ -135,6 +135,24 @@ impl TryFrom<u16> for PowerLevel {
     }
 }
 
+impl From<bool> for PowerLevel {
+    /// `true` is [PowerLevel::Enabled], and `false` is [PowerLevel::Standby].
+    fn from(on: bool) -> PowerLevel {
+        if on {
+            PowerLevel::Enabled
+        } else {
+            PowerLevel::Standby
+        }
+    }
+}
+
+impl From<PowerLevel> for bool {
+    /// Whether the level is anything other than [PowerLevel::Standby].
+    fn from(level: PowerLevel) -> bool {
+        level != PowerLevel::Standby
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct EchoPayload(pub [u8; 64]);
@@ -340,11 +358,11 @@ where
     }
 }
 
-impl<T> LittleEndianWriter<&Box<[HSBK; 82]>> for T
+impl<T, const N: usize> LittleEndianWriter<&Box<[HSBK; N]>> for T
 where
     T: WriteBytesExt,
 {
-    fn write_val(&mut self, v: &Box<[HSBK; 82]>) -> Result<(), io::Error> {
+    fn write_val(&mut self, v: &Box<[HSBK; N]>) -> Result<(), io::Error> {
         for elem in &**v {
             self.write_val(*elem)?;
         }
@@ -352,6 +370,44 @@ where
     }
 }
 
+impl<T> LittleEndianWriter<TileInfo> for T
+where
+    T: WriteBytesExt,
+{
+    fn write_val(&mut self, v: TileInfo) -> Result<(), io::Error> {
+        self.write_val(v.accel_meas_x)?;
+        self.write_val(v.accel_meas_y)?;
+        self.write_val(v.accel_meas_z)?;
+        self.write_val(0i16)?;
+        self.write_val(v.user_x)?;
+        self.write_val(v.user_y)?;
+        self.write_val(v.width)?;
+        self.write_val(v.height)?;
+        self.write_val(0u8)?;
+        self.write_val(v.device_version_vendor)?;
+        self.write_val(v.device_version_product)?;
+        self.write_val(0u32)?;
+        self.write_val(v.firmware_build)?;
+        self.write_val(0u64)?;
+        self.write_val(v.firmware_version_minor)?;
+        self.write_val(v.firmware_version_major)?;
+        self.write_val(0u32)?;
+        Ok(())
+    }
+}
+
+impl<T> LittleEndianWriter<&Box<[TileInfo; 16]>> for T
+where
+    T: WriteBytesExt,
+{
+    fn write_val(&mut self, v: &Box<[TileInfo; 16]>) -> Result<(), io::Error> {
+        for elem in &**v {
+            self.write_val(elem.clone())?;
+        }
+        Ok(())
+    }
+}
+
 impl<T> LittleEndianWriter<&[u8; 32]> for T
 where
     T: WriteBytesExt,
@@ -392,6 +448,13 @@ macro_rules! derive_reader {
 
 derive_reader! { read_u32: u32, read_u16: u16, read_i16: i16, read_u64: u64, read_f32: f32 }
 
+#[cfg(fuzzing)]
+impl<R: ReadBytesExt> LittleEndianReader<ComparableFloat> for R {
+    fn read_val(&mut self) -> Result<ComparableFloat, io::Error> {
+        Ok(ComparableFloat(self.read_f32::<LittleEndian>()?))
+    }
+}
+
 impl<R: ReadBytesExt> LittleEndianReader<u8> for R {
     fn read_val(&mut self) -> Result<u8, io::Error> {
         self.read_u8()
@@ -449,14 +512,14 @@ impl<R: ReadBytesExt> LittleEndianReader<[u32; 8]> for R {
     }
 }
 
-impl<R: ReadBytesExt> LittleEndianReader<[HSBK; 82]> for R {
-    fn read_val(&mut self) -> Result<[HSBK; 82], io::Error> {
+impl<R: ReadBytesExt, const N: usize> LittleEndianReader<[HSBK; N]> for R {
+    fn read_val(&mut self) -> Result<[HSBK; N], io::Error> {
         let mut data = [HSBK {
             hue: 0,
             saturation: 0,
             brightness: 0,
             kelvin: 0,
-        }; 82];
+        }; N];
         for x in &mut data {
             *x = self.read_val()?;
         }
@@ -480,6 +543,52 @@ impl<R: ReadBytesExt> LittleEndianReader<HSBK> for R {
     }
 }
 
+impl<R: ReadBytesExt> LittleEndianReader<TileInfo> for R {
+    fn read_val(&mut self) -> Result<TileInfo, io::Error> {
+        let accel_meas_x = self.read_val()?;
+        let accel_meas_y = self.read_val()?;
+        let accel_meas_z = self.read_val()?;
+        let _reserved: i16 = self.read_val()?;
+        let user_x = self.read_val()?;
+        let user_y = self.read_val()?;
+        let width = self.read_val()?;
+        let height = self.read_val()?;
+        let _reserved: u8 = self.read_val()?;
+        let device_version_vendor = self.read_val()?;
+        let device_version_product = self.read_val()?;
+        let _reserved: u32 = self.read_val()?;
+        let firmware_build = self.read_val()?;
+        let _reserved: u64 = self.read_val()?;
+        let firmware_version_minor = self.read_val()?;
+        let firmware_version_major = self.read_val()?;
+        let _reserved: u32 = self.read_val()?;
+        Ok(TileInfo {
+            accel_meas_x,
+            accel_meas_y,
+            accel_meas_z,
+            user_x,
+            user_y,
+            width,
+            height,
+            device_version_vendor,
+            device_version_product,
+            firmware_build,
+            firmware_version_minor,
+            firmware_version_major,
+        })
+    }
+}
+
+impl<R: ReadBytesExt> LittleEndianReader<[TileInfo; 16]> for R {
+    fn read_val(&mut self) -> Result<[TileInfo; 16], io::Error> {
+        let mut data: [TileInfo; 16] = Default::default();
+        for x in &mut data {
+            *x = self.read_val()?;
+        }
+        Ok(data)
+    }
+}
+
 impl<R: ReadBytesExt> LittleEndianReader<LifxIdent> for R {
     fn read_val(&mut self) -> Result<LifxIdent, io::Error> {
         let mut val = [0; 16];
@@ -596,7 +705,7 @@ pub enum Service {
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum PowerLevel {
     Standby = 0,
-    Half = 32500, 
+    Half = 32500,
     Enabled = 65535,
 }
 
@@ -653,8 +762,8 @@ pub enum MultiZoneEffectType {
 ///
 /// This enum lists all of the LIFX message types known to this library.
 ///
-/// Note that other message types exist, but are not officially documented (and so are not
-/// available here).
+/// Note that other message types exist, but are not officially documented (and so, apart from
+/// [Message::SetReboot], are not available here).
 #[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Message {
@@ -881,6 +990,13 @@ pub enum Message {
         downtime: u64,
     },
 
+    /// Reboots the device.
+    ///
+    /// This message is not officially documented.  The device does not send a reply.
+    ///
+    /// Message type 38
+    SetReboot,
+
     /// Response to any message sent with ack_required set to 1. See message header frame address.
     ///
     /// (Note that technically this message has no payload, but the frame sequence number is stored
@@ -959,6 +1075,16 @@ pub enum Message {
     /// Message type 59
     EchoResponse { payload: EchoPayload },
 
+    /// Sent by a device in response to a message it doesn't support.
+    ///
+    /// Only sent by newer firmware.  Older devices ignore messages they don't support.
+    ///
+    /// Message type 223
+    StateUnhandled {
+        /// The type of the message that wasn't handled
+        unhandled_type: u16,
+    },
+
     /// Sent by a client to obtain the light state.
     ///
     /// Causes the device to transmit a [Message::LightState] message.
@@ -1233,6 +1359,73 @@ pub enum Message {
         colors: Box<[HSBK; 82]>,
     },
 
+    /// Causes the device to transmit a [Message::StateDeviceChain] message.
+    ///
+    /// This requires the device has the `matrix` capability.
+    ///
+    /// Message type 701
+    GetDeviceChain,
+
+    /// Information about each tile in a chain of devices.
+    ///
+    /// Message type 702
+    StateDeviceChain {
+        /// The index of the first tile in `tile_devices`
+        start_index: u8,
+        tile_devices: Box<[TileInfo; 16]>,
+        /// How many of `tile_devices` are actually present
+        tile_devices_count: u8,
+    },
+
+    /// Causes the device to transmit a [Message::StateTileState64] message for each requested tile.
+    ///
+    /// Message type 707
+    GetTileState64 {
+        /// The first tile to get colors from
+        tile_index: u8,
+        /// How many tiles to get colors from, starting at `tile_index`
+        length: u8,
+        reserved: u8,
+        /// The column of the first pixel to return
+        x: u8,
+        /// The row of the first pixel to return
+        y: u8,
+        /// The width of the rectangle of pixels to return
+        width: u8,
+    },
+
+    /// The colors of up to 64 pixels on one tile.
+    ///
+    /// Message type 711
+    StateTileState64 {
+        tile_index: u8,
+        reserved: u8,
+        x: u8,
+        y: u8,
+        width: u8,
+        colors: Box<[HSBK; 64]>,
+    },
+
+    /// Sets the colors of up to 64 pixels on one or more tiles.
+    ///
+    /// Message type 715
+    SetTileState64 {
+        /// The first tile to change
+        tile_index: u8,
+        /// How many tiles to change, starting at `tile_index`
+        length: u8,
+        reserved: u8,
+        /// The column of the first pixel to change
+        x: u8,
+        /// The row of the first pixel to change
+        y: u8,
+        /// The width of the rectangle of pixels being set
+        width: u8,
+        /// Transition time, in milliseconds
+        duration: u32,
+        colors: Box<[HSBK; 64]>,
+    },
+
     /// Get the power state of a relay
     ///
     /// This requires the device has the `relays` capability.
@@ -1294,6 +1487,7 @@ impl Message {
             Message::StateVersion { .. } => 33,
             Message::GetInfo => 34,
             Message::StateInfo { .. } => 35,
+            Message::SetReboot => 38,
             Message::Acknowledgement { .. } => 45,
             Message::GetLocation => 48,
             Message::SetLocation { .. } => 49,
@@ -1303,6 +1497,7 @@ impl Message {
             Message::StateGroup { .. } => 53,
             Message::EchoRequest { .. } => 58,
             Message::EchoResponse { .. } => 59,
+            Message::StateUnhandled { .. } => 223,
             Message::LightGet => 101,
             Message::LightSetColor { .. } => 102,
             Message::SetWaveform { .. } => 103,
@@ -1332,12 +1527,91 @@ impl Message {
             Message::SetExtendedColorZones { .. } => 510,
             Message::GetExtendedColorZones => 511,
             Message::StateExtendedColorZones { .. } => 512,
+            Message::GetDeviceChain => 701,
+            Message::StateDeviceChain { .. } => 702,
+            Message::GetTileState64 { .. } => 707,
+            Message::StateTileState64 { .. } => 711,
+            Message::SetTileState64 { .. } => 715,
             Message::RelayGetPower { .. } => 816,
             Message::RelaySetPower { .. } => 817,
             Message::RelayStatePower { .. } => 818,
         }
     }
 
+    /// The name of this message's type, such as `"LightSetColor"`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Message::GetService => "GetService",
+            Message::StateService { .. } => "StateService",
+            Message::GetHostInfo => "GetHostInfo",
+            Message::StateHostInfo { .. } => "StateHostInfo",
+            Message::GetHostFirmware => "GetHostFirmware",
+            Message::StateHostFirmware { .. } => "StateHostFirmware",
+            Message::GetWifiInfo => "GetWifiInfo",
+            Message::StateWifiInfo { .. } => "StateWifiInfo",
+            Message::GetWifiFirmware => "GetWifiFirmware",
+            Message::StateWifiFirmware { .. } => "StateWifiFirmware",
+            Message::GetPower => "GetPower",
+            Message::SetPower { .. } => "SetPower",
+            Message::StatePower { .. } => "StatePower",
+            Message::GetLabel => "GetLabel",
+            Message::SetLabel { .. } => "SetLabel",
+            Message::StateLabel { .. } => "StateLabel",
+            Message::GetVersion => "GetVersion",
+            Message::StateVersion { .. } => "StateVersion",
+            Message::GetInfo => "GetInfo",
+            Message::StateInfo { .. } => "StateInfo",
+            Message::SetReboot => "SetReboot",
+            Message::Acknowledgement { .. } => "Acknowledgement",
+            Message::GetLocation => "GetLocation",
+            Message::SetLocation { .. } => "SetLocation",
+            Message::StateLocation { .. } => "StateLocation",
+            Message::GetGroup => "GetGroup",
+            Message::SetGroup { .. } => "SetGroup",
+            Message::StateGroup { .. } => "StateGroup",
+            Message::EchoRequest { .. } => "EchoRequest",
+            Message::EchoResponse { .. } => "EchoResponse",
+            Message::StateUnhandled { .. } => "StateUnhandled",
+            Message::LightGet => "LightGet",
+            Message::LightSetColor { .. } => "LightSetColor",
+            Message::SetWaveform { .. } => "SetWaveform",
+            Message::LightState { .. } => "LightState",
+            Message::LightGetPower => "LightGetPower",
+            Message::LightSetPower { .. } => "LightSetPower",
+            Message::LightStatePower { .. } => "LightStatePower",
+            Message::SetWaveformOptional { .. } => "SetWaveformOptional",
+            Message::LightGetInfrared => "LightGetInfrared",
+            Message::LightStateInfrared { .. } => "LightStateInfrared",
+            Message::LightSetInfrared { .. } => "LightSetInfrared",
+            Message::LightGetHevCycle => "LightGetHevCycle",
+            Message::LightSetHevCycle { .. } => "LightSetHevCycle",
+            Message::LightStateHevCycle { .. } => "LightStateHevCycle",
+            Message::LightGetHevCycleConfiguration => "LightGetHevCycleConfiguration",
+            Message::LightSetHevCycleConfiguration { .. } => "LightSetHevCycleConfiguration",
+            Message::LightStateHevCycleConfiguration { .. } => "LightStateHevCycleConfiguration",
+            Message::LightGetLastHevCycleResult => "LightGetLastHevCycleResult",
+            Message::LightStateLastHevCycleResult { .. } => "LightStateLastHevCycleResult",
+            Message::SetColorZones { .. } => "SetColorZones",
+            Message::GetColorZones { .. } => "GetColorZones",
+            Message::StateZone { .. } => "StateZone",
+            Message::StateMultiZone { .. } => "StateMultiZone",
+            Message::GetMultiZoneEffect => "GetMultiZoneEffect",
+            Message::SetMultiZoneEffect { .. } => "SetMultiZoneEffect",
+            Message::StateMultiZoneEffect { .. } => "StateMultiZoneEffect",
+            Message::SetExtendedColorZones { .. } => "SetExtendedColorZones",
+            Message::GetExtendedColorZones => "GetExtendedColorZones",
+            Message::StateExtendedColorZones { .. } => "StateExtendedColorZones",
+            Message::GetDeviceChain => "GetDeviceChain",
+            Message::StateDeviceChain { .. } => "StateDeviceChain",
+            Message::GetTileState64 { .. } => "GetTileState64",
+            Message::StateTileState64 { .. } => "StateTileState64",
+            Message::SetTileState64 { .. } => "SetTileState64",
+            Message::RelayGetPower { .. } => "RelayGetPower",
+            Message::RelaySetPower { .. } => "RelaySetPower",
+            Message::RelayStatePower { .. } => "RelayStatePower",
+        }
+    }
+
     /// Tries to parse the payload in a [RawMessage], based on its message type.
     pub fn from_raw(msg: &RawMessage) -> Result<Message, Error> {
         match msg.protocol_header.typ {
@@ -1401,6 +1675,7 @@ impl Message {
                 uptime: u64,
                 downtime: u64
             )),
+            38 => Ok(Message::SetReboot),
             45 => Ok(Message::Acknowledgement {
                 seq: msg.frame_addr.sequence,
             }),
@@ -1436,6 +1711,7 @@ impl Message {
             )),
             58 => Ok(unpack!(msg, EchoRequest, payload: EchoPayload)),
             59 => Ok(unpack!(msg, EchoResponse, payload: EchoPayload)),
+            223 => Ok(unpack!(msg, StateUnhandled, unhandled_type: u16)),
             101 => Ok(Message::LightGet),
             102 => Ok(unpack!(
                 msg,
@@ -1586,6 +1862,46 @@ impl Message {
                 colors_count: u8,
                 colors: [HSBK; 82]
             )),
+            701 => Ok(Message::GetDeviceChain),
+            702 => Ok(unpack!(
+                msg,
+                StateDeviceChain,
+                start_index: u8,
+                tile_devices: [TileInfo; 16],
+                tile_devices_count: u8
+            )),
+            707 => Ok(unpack!(
+                msg,
+                GetTileState64,
+                tile_index: u8,
+                length: u8,
+                reserved: u8,
+                x: u8,
+                y: u8,
+                width: u8
+            )),
+            711 => Ok(unpack!(
+                msg,
+                StateTileState64,
+                tile_index: u8,
+                reserved: u8,
+                x: u8,
+                y: u8,
+                width: u8,
+                colors: [HSBK; 64]
+            )),
+            715 => Ok(unpack!(
+                msg,
+                SetTileState64,
+                tile_index: u8,
+                length: u8,
+                reserved: u8,
+                x: u8,
+                y: u8,
+                width: u8,
+                duration: u32,
+                colors: [HSBK; 64]
+            )),
             816 => Ok(unpack!(msg, RelayGetPower, relay_index: u8)),
             817 => Ok(unpack!(msg, RelaySetPower, relay_index: u8, level: u16)),
             818 => Ok(unpack!(msg, RelayStatePower, relay_index: u8, level: u16)),
@@ -1594,6 +1910,34 @@ impl Message {
     }
 }
 
+/// Information about one tile in a device chain, as reported by [Message::StateDeviceChain].
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct TileInfo {
+    /// Accelerometer readings, which can be used to work out the tile's orientation
+    pub accel_meas_x: i16,
+    pub accel_meas_y: i16,
+    pub accel_meas_z: i16,
+    /// The tile's position in the user's layout, in units of tile widths
+    #[cfg(not(fuzzing))]
+    pub user_x: f32,
+    #[cfg(fuzzing)]
+    pub user_x: ComparableFloat,
+    #[cfg(not(fuzzing))]
+    pub user_y: f32,
+    #[cfg(fuzzing)]
+    pub user_y: ComparableFloat,
+    /// The number of pixels in each row
+    pub width: u8,
+    /// The number of rows
+    pub height: u8,
+    pub device_version_vendor: u32,
+    pub device_version_product: u32,
+    pub firmware_build: u64,
+    pub firmware_version_minor: u16,
+    pub firmware_version_major: u16,
+}
+
 /// Bulb color (Hue-Saturation-Brightness-Kelvin)
 ///
 /// # Notes:
@@ -1610,6 +1954,7 @@ impl Message {
 /// To display "pure" colors, set saturation to full (65535).
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HSBK {
     pub hue: u16,
     pub saturation: u16,
@@ -1794,7 +2139,7 @@ impl Frame {
         let mut d: u16 = (<u16 as From<u8>>::from(self.origin) & 0b11) << 14;
         d += if self.tagged { 1 } else { 0 } << 13;
         d += if self.addressable { 1 } else { 0 } << 12;
-        d += (self.protocol & 0b1111_1111_1111) as u16;
+        d += self.protocol & 0b1111_1111_1111;
 
         v.write_u16::<LittleEndian>(d)?;
 
@@ -1997,6 +2342,7 @@ impl RawMessage {
             | Message::GetLabel
             | Message::GetVersion
             | Message::GetInfo
+            | Message::SetReboot
             | Message::Acknowledgement { .. }
             | Message::GetLocation
             | Message::GetGroup
@@ -2007,7 +2353,8 @@ impl RawMessage {
             | Message::LightGetHevCycleConfiguration
             | Message::LightGetLastHevCycleResult
             | Message::GetMultiZoneEffect
-            | Message::GetExtendedColorZones => {
+            | Message::GetExtendedColorZones
+            | Message::GetDeviceChain => {
                 // these types have no payload
             }
             Message::SetColorZones {
@@ -2226,6 +2573,9 @@ impl RawMessage {
             Message::EchoResponse { payload } => {
                 v.write_val(payload)?;
             }
+            Message::StateUnhandled { unhandled_type } => {
+                v.write_val(unhandled_type)?;
+            }
             Message::LightSetColor {
                 reserved,
                 color,
@@ -2336,6 +2686,64 @@ impl RawMessage {
                 v.write_val(colors_count)?;
                 v.write_val(&colors)?;
             }
+            Message::StateDeviceChain {
+                start_index,
+                tile_devices,
+                tile_devices_count,
+            } => {
+                v.write_val(start_index)?;
+                v.write_val(&tile_devices)?;
+                v.write_val(tile_devices_count)?;
+            }
+            Message::GetTileState64 {
+                tile_index,
+                length,
+                reserved,
+                x,
+                y,
+                width,
+            } => {
+                v.write_val(tile_index)?;
+                v.write_val(length)?;
+                v.write_val(reserved)?;
+                v.write_val(x)?;
+                v.write_val(y)?;
+                v.write_val(width)?;
+            }
+            Message::StateTileState64 {
+                tile_index,
+                reserved,
+                x,
+                y,
+                width,
+                colors,
+            } => {
+                v.write_val(tile_index)?;
+                v.write_val(reserved)?;
+                v.write_val(x)?;
+                v.write_val(y)?;
+                v.write_val(width)?;
+                v.write_val(&colors)?;
+            }
+            Message::SetTileState64 {
+                tile_index,
+                length,
+                reserved,
+                x,
+                y,
+                width,
+                duration,
+                colors,
+            } => {
+                v.write_val(tile_index)?;
+                v.write_val(length)?;
+                v.write_val(reserved)?;
+                v.write_val(x)?;
+                v.write_val(y)?;
+                v.write_val(width)?;
+                v.write_val(duration)?;
+                v.write_val(&colors)?;
+            }
             Message::RelayGetPower { relay_index } => {
                 v.write_val(relay_index)?;
             }
@@ -2403,6 +2811,11 @@ impl RawMessage {
     pub fn unpack(v: &[u8]) -> Result<RawMessage, Error> {
         let mut start = 0;
         let frame = Frame::unpack(v)?;
+        if !frame.addressable {
+            return Err(Error::ProtocolError(
+                "Unpacked frame wasn't addressable".to_owned(),
+            ));
+        }
         frame.validate();
         start += Frame::packed_size();
         let addr = FrameAddress::unpack(&v[start..])?;
@@ -2412,6 +2825,13 @@ impl RawMessage {
         proto.validate();
         start += ProtocolHeader::packed_size();
 
+        if (frame.size as usize) < start || frame.size as usize > v.len() {
+            return Err(Error::ProtocolError(format!(
+                "Unpacked frame had size {}, but {} bytes were given",
+                frame.size,
+                v.len()
+            )));
+        }
         let body = Vec::from(&v[start..(frame.size as usize)]);
 
         Ok(RawMessage {
@@ -2771,6 +3191,108 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_tile_state_64_roundtrip() {
+        let mut colors = Box::new(
+            [HSBK {
+                hue: 0,
+                saturation: 0,
+                brightness: 0,
+                kelvin: 3500,
+            }; 64],
+        );
+        colors[63].hue = 1234;
+        let msg = Message::SetTileState64 {
+            tile_index: 2,
+            length: 1,
+            reserved: 0,
+            x: 0,
+            y: 0,
+            width: 8,
+            duration: 500,
+            colors,
+        };
+        let raw = RawMessage::build(&BuildOptions::default(), msg.clone()).unwrap();
+        let bytes = raw.pack().unwrap();
+        assert_eq!(bytes.len(), 36 + 10 + 64 * 8);
+
+        let unpacked = RawMessage::unpack(&bytes).unwrap();
+        assert_eq!(unpacked.protocol_header.typ, 715);
+        assert_eq!(Message::from_raw(&unpacked).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_state_device_chain_roundtrip() {
+        let mut tile_devices: Box<[TileInfo; 16]> = Box::default();
+        tile_devices[0].width = 8;
+        tile_devices[0].height = 8;
+        tile_devices[0].user_x = 1.5;
+        let msg = Message::StateDeviceChain {
+            start_index: 0,
+            tile_devices,
+            tile_devices_count: 1,
+        };
+        let raw = RawMessage::build(&BuildOptions::default(), msg.clone()).unwrap();
+        let bytes = raw.pack().unwrap();
+        assert_eq!(bytes.len(), 36 + 2 + 16 * 55);
+
+        let unpacked = RawMessage::unpack(&bytes).unwrap();
+        assert_eq!(Message::from_raw(&unpacked).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_set_reboot_roundtrip() {
+        let raw = RawMessage::build(&BuildOptions::default(), Message::SetReboot).unwrap();
+        let bytes = raw.pack().unwrap();
+        assert_eq!(bytes.len(), 36);
+
+        let unpacked = RawMessage::unpack(&bytes).unwrap();
+        assert_eq!(unpacked.protocol_header.typ, 38);
+        assert_eq!(Message::from_raw(&unpacked).unwrap(), Message::SetReboot);
+    }
+
+    #[test]
+    fn test_state_unhandled_roundtrip() {
+        let msg = Message::StateUnhandled {
+            unhandled_type: 510,
+        };
+        let raw = RawMessage::build(&BuildOptions::default(), msg.clone()).unwrap();
+        let bytes = raw.pack().unwrap();
+        assert_eq!(bytes.len(), 38);
+
+        let unpacked = RawMessage::unpack(&bytes).unwrap();
+        assert_eq!(unpacked.protocol_header.typ, 223);
+        assert_eq!(Message::from_raw(&unpacked).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_message_name() {
+        assert_eq!(Message::GetService.name(), "GetService");
+        let msg = Message::StateUnhandled { unhandled_type: 2 };
+        assert_eq!(msg.name(), "StateUnhandled");
+    }
+
+    #[test]
+    fn test_power_level_bool() {
+        assert_eq!(PowerLevel::from(true), PowerLevel::Enabled);
+        assert_eq!(PowerLevel::from(false), PowerLevel::Standby);
+        assert!(bool::from(PowerLevel::Enabled));
+        assert!(bool::from(PowerLevel::Half));
+        assert!(!bool::from(PowerLevel::Standby));
+    }
+
+    #[test]
+    fn test_unpack_truncated() {
+        let raw = RawMessage::build(&BuildOptions::default(), Message::GetLabel).unwrap();
+        let bytes = raw.pack().unwrap();
+        assert!(RawMessage::unpack(&bytes[..bytes.len() - 1]).is_err());
+
+        // a frame claiming to be smaller than its own headers
+        let mut bytes = bytes;
+        bytes[0] = 8;
+        assert!(RawMessage::unpack(&bytes).is_err());
+    }
+
     #[test]
     fn test_lifx_string() {
         let s = CStr::from_bytes_with_nul(b"hello\0").unwrap();
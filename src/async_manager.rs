@@ -0,0 +1,369 @@
+use crate::bulb_manager::{
+    apply_message, bind_socket, check_sent, discovery_addrs, discovery_packet, BulbEvent, BulbInfo,
+    LifxError, Manager, ManagerConfig, RawMessageCallback, SharedState, WorkerError,
+};
+use lifx_core::{Message, PowerLevel, HSBK};
+use log::{debug, info};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc, PoisonError};
+use std::time::Instant;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+const WORKER_ERROR_BACKLOG: usize = 64;
+
+/// An async counterpart to [`Manager`], built on tokio.
+///
+/// Incoming messages are handled by a task started with `tokio::spawn`, so this must be
+/// constructed from within a tokio runtime.  The task is aborted when the manager is dropped.
+pub struct AsyncManager {
+    bulbs: Arc<Mutex<HashMap<u64, BulbInfo>>>,
+    /// When discovery messages were last sent.
+    last_discovery: std::sync::Mutex<Instant>,
+    pub sock: Arc<UdpSocket>,
+    shared: SharedState,
+    config: ManagerConfig,
+    errors: Receiver<WorkerError>,
+    worker: JoinHandle<()>,
+}
+
+impl AsyncManager {
+    pub async fn new() -> Result<AsyncManager, LifxError> {
+        Self::with_config(ManagerConfig::default()).await
+    }
+
+    /// Creates a manager with the given config.  See [`Manager::with_config`].
+    ///
+    /// The background threads the config can ask for aren't available here, so
+    /// [`ManagerConfig::auto_prune`], [`ManagerConfig::retry`] and
+    /// [`ManagerConfig::recv_timeout`] are ignored.  So is [`ManagerConfig::recover_poisoned`],
+    /// since the bulb table is behind a tokio mutex, which can't be poisoned.
+    pub async fn with_config(config: ManagerConfig) -> Result<AsyncManager, LifxError> {
+        let sock = bind_socket(&config)?;
+        // tokio needs the socket to be non-blocking before it can take it over
+        sock.set_nonblocking(true)?;
+        let sock: UdpSocket = UdpSocket::from_std(sock)?;
+        let sock: Arc<UdpSocket> = Arc::new(sock);
+
+        let bulbs: Arc<Mutex<HashMap<u64, BulbInfo>>> = Arc::new(Mutex::new(HashMap::new()));
+        let shared: SharedState = SharedState::from_config(&config);
+        let (error_tx, errors) = channel(WORKER_ERROR_BACKLOG);
+
+        let worker = tokio::spawn(Self::worker(
+            sock.clone(),
+            bulbs.clone(),
+            shared.clone(),
+            error_tx,
+        ));
+
+        Ok(AsyncManager {
+            bulbs,
+            last_discovery: std::sync::Mutex::new(Instant::now()),
+            sock,
+            shared,
+            config,
+            errors,
+            worker,
+        })
+    }
+
+    async fn worker(
+        recv_sock: Arc<UdpSocket>,
+        receiver_bulbs: Arc<Mutex<HashMap<u64, BulbInfo>>>,
+        shared: SharedState,
+        errors: Sender<WorkerError>,
+    ) {
+        // if nobody is draining the channel, drop the error rather than stalling the worker
+        let report = |e: WorkerError| {
+            let _ = errors.try_send(e);
+        };
+        let mut buf = [0; 1024];
+        loop {
+            match recv_sock.recv_from(&mut buf).await {
+                Ok((nbytes, addr)) => {
+                    let Some(raw) = Manager::unpack_datagram(&buf[0..nbytes], addr, &report) else {
+                        continue;
+                    };
+                    shared.raw_hook.call(&raw, addr);
+                    let mut bulbs = receiver_bulbs.lock().await;
+                    if let Err(e) = apply_message(&mut bulbs, &shared, raw, addr) {
+                        report(e);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    let error = WorkerError::Recv(e);
+                    let fatal = error.is_fatal();
+                    report(error);
+                    if fatal {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Errors reported by the background worker task.
+    pub fn errors(&mut self) -> &mut Receiver<WorkerError> {
+        &mut self.errors
+    }
+
+    /// The source identifier stamped on every message this manager sends.
+    pub fn source(&self) -> u32 {
+        self.shared.source
+    }
+
+    /// Whether the background worker task is still receiving messages.
+    pub fn is_alive(&self) -> bool {
+        !self.worker.is_finished()
+    }
+
+    /// When discovery messages were last sent by [`AsyncManager::discover`], or when the manager
+    /// was created if they never have been.
+    pub fn last_discovery(&self) -> Instant {
+        *self
+            .last_discovery
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub async fn discover(&self) -> Result<(), LifxError> {
+        info!("Doing discovery");
+
+        let bytes = discovery_packet(self.shared.source)?;
+        let addrs = discovery_addrs(
+            self.config.broadcast_port,
+            self.config.interfaces.as_deref(),
+            self.config.limited_broadcast,
+        )?;
+        for addr in addrs {
+            debug!("Discovering bulbs on {:?}", addr);
+            check_sent(self.sock.send_to(&bytes, addr).await?, bytes.len())?;
+        }
+
+        *self
+            .last_discovery
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Instant::now();
+
+        Ok(())
+    }
+
+    /// Limits how many messages per second are sent to each bulb.  Zero disables limiting.
+    ///
+    /// Sends that would exceed the limit fail with [`LifxError::RateLimited`].
+    pub fn set_rate_limit(&self, msgs_per_sec: u32) {
+        self.shared
+            .rate_limit
+            .store(msgs_per_sec, Ordering::Relaxed);
+    }
+
+    /// Returns a channel that receives a [`BulbEvent`] whenever a bulb's state changes.
+    ///
+    /// Every call returns a new receiver, and each receiver gets its own copy of every event.
+    pub fn subscribe(&self) -> mpsc::Receiver<BulbEvent> {
+        self.shared.subscribers.subscribe()
+    }
+
+    /// Registers `callback` to be called with every message received from a bulb, before the
+    /// manager handles it itself.  See [`Manager::on_raw_message`].
+    ///
+    /// The callback runs on the worker task, so it mustn't block.
+    pub fn on_raw_message(&self, callback: RawMessageCallback) {
+        self.shared.raw_hook.set(callback);
+    }
+
+    pub async fn refresh(&self) -> Result<(), LifxError> {
+        let mut packets: Vec<(Vec<u8>, SocketAddr)> = Vec::new();
+        {
+            let bulbs = self.bulbs.lock().await;
+            for bulb in bulbs.values() {
+                for payload in bulb.missing_info_messages() {
+                    match bulb.build_packet(payload) {
+                        Ok(bytes) => packets.push((bytes, bulb.addr)),
+                        // whatever didn't get sent is still stale, and will be retried next time
+                        Err(LifxError::RateLimited) => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+        for (bytes, addr) in packets {
+            check_sent(self.sock.send_to(&bytes, addr).await?, bytes.len())?;
+        }
+        Ok(())
+    }
+
+    pub async fn add_bulb(&self, addr: SocketAddr) -> Result<(), LifxError> {
+        let bytes = discovery_packet(self.shared.source)?;
+        info!("Attempting connection to: {:?}", addr);
+        check_sent(self.sock.send_to(&bytes, addr).await?, bytes.len())?;
+        Ok(())
+    }
+
+    /// Calls `f` with every known bulb.
+    ///
+    /// The bulb table is locked while `f` runs, so `f` shouldn't block for long.
+    pub async fn for_each_bulb<F: FnMut(&BulbInfo)>(&self, f: F) {
+        let bulbs = self.bulbs.lock().await;
+        bulbs.values().for_each(f);
+    }
+
+    /// Calls `f` with the bulb with the given target, returning its result.
+    ///
+    /// The bulb table is locked while `f` runs, so `f` shouldn't block for long.
+    pub async fn with_bulb<R>(
+        &self,
+        target: u64,
+        f: impl FnOnce(&BulbInfo) -> R,
+    ) -> Result<R, LifxError> {
+        let bulbs = self.bulbs.lock().await;
+        let bulb = bulbs.get(&target).ok_or(LifxError::BulbNotFound(target))?;
+        Ok(f(bulb))
+    }
+
+    /// Sends `payload` to the bulb with the given target.
+    async fn send(&self, target: u64, payload: Message) -> Result<(), LifxError> {
+        let (bytes, addr) = {
+            let bulbs = self.bulbs.lock().await;
+            let bulb = bulbs.get(&target).ok_or(LifxError::BulbNotFound(target))?;
+            (bulb.build_packet(payload)?, bulb.addr)
+        };
+        check_sent(self.sock.send_to(&bytes, addr).await?, bytes.len())?;
+        Ok(())
+    }
+
+    /// Sends `msg` to the bulb with the given target.  See [`Manager::send_message`].
+    pub async fn send_message(&self, target: u64, msg: Message) -> Result<(), LifxError> {
+        self.send(target, msg).await
+    }
+
+    /// Turns the bulb off if it's on, and on otherwise.  See [`BulbInfo::toggle_bulb`].
+    pub async fn toggle_bulb(&self, target: u64) -> Result<(), LifxError> {
+        let level = self.with_bulb(target, BulbInfo::toggled_level).await?;
+        self.send(target, Message::SetPower { level }).await
+    }
+
+    pub async fn set_power(&self, target: u64, level: PowerLevel) -> Result<(), LifxError> {
+        self.send(target, Message::SetPower { level }).await
+    }
+
+    pub async fn set_power_duration(
+        &self,
+        target: u64,
+        level: u16,
+        duration: u32,
+    ) -> Result<(), LifxError> {
+        self.send(target, Message::LightSetPower { level, duration })
+            .await
+    }
+
+    pub async fn set_bulb_color(
+        &self,
+        target: u64,
+        color: HSBK,
+        duration: u32,
+    ) -> Result<(), LifxError> {
+        let payload: Message = Message::LightSetColor {
+            reserved: 0,
+            color,
+            duration,
+        };
+        self.send(target, payload).await
+    }
+}
+
+impl Drop for AsyncManager {
+    fn drop(&mut self) {
+        self.worker.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lifx_core::{BuildOptions, RawMessage, Service};
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    /// Receives the next message sent to `bulb`, along with the target it was addressed to.
+    async fn next_message(bulb: &UdpSocket) -> (Option<u64>, Message) {
+        let mut buf = [0; 1024];
+        let recv = bulb.recv_from(&mut buf);
+        let (nbytes, _) = tokio::time::timeout(Duration::from_secs(1), recv)
+            .await
+            .expect("timed out waiting for a message")
+            .unwrap();
+        let raw = RawMessage::unpack(&buf[..nbytes]).unwrap();
+        let target = Some(raw.frame_addr.target).filter(|&target| target != 0);
+        (target, Message::from_raw(&raw).unwrap())
+    }
+
+    /// Runs `test` to completion on a new single-threaded runtime.
+    fn block_on(test: impl std::future::Future<Output = ()>) {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(test)
+    }
+
+    #[test]
+    fn test_add_bulb_and_send() {
+        block_on(add_bulb_and_send())
+    }
+
+    async fn add_bulb_and_send() {
+        let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+        let mgr = AsyncManager::with_config(ManagerConfig {
+            bind_addr: localhost,
+            ..ManagerConfig::default()
+        })
+        .await
+        .unwrap();
+        let bulb = UdpSocket::bind(localhost).await.unwrap();
+        let bulb_addr = bulb.local_addr().unwrap();
+
+        mgr.add_bulb(bulb_addr).await.unwrap();
+        assert_eq!(next_message(&bulb).await, (None, Message::GetService));
+
+        // answer, so the manager learns about the bulb
+        let options = BuildOptions {
+            target: Some(0x1234),
+            source: mgr.source(),
+            ..BuildOptions::default()
+        };
+        let reply = Message::StateService {
+            service: Service::UDP,
+            port: bulb_addr.port().into(),
+        };
+        let packet = RawMessage::build(&options, reply).unwrap().pack().unwrap();
+        bulb.send_to(&packet, mgr.sock.local_addr().unwrap())
+            .await
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while mgr.with_bulb(0x1234, |bulb| bulb.addr).await.is_err() {
+            assert!(Instant::now() < deadline, "bulb was never added");
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        mgr.send_message(0x1234, Message::GetPower).await.unwrap();
+        assert_eq!(next_message(&bulb).await, (Some(0x1234), Message::GetPower));
+        // the power level isn't known yet, so toggling turns it on
+        mgr.toggle_bulb(0x1234).await.unwrap();
+        let level = PowerLevel::Enabled;
+        assert_eq!(
+            next_message(&bulb).await,
+            (Some(0x1234), Message::SetPower { level })
+        );
+        assert!(matches!(
+            mgr.send_message(0x5678, Message::GetPower).await,
+            Err(LifxError::BulbNotFound(0x5678))
+        ));
+    }
+}
@@ -0,0 +1,488 @@
+use crate::bulb_manager::LifxError;
+use lifx_core::HSBK;
+
+/// Fully saturated red, at full brightness.
+pub const RED: HSBK = HSBK {
+    hue: 0,
+    saturation: u16::MAX,
+    brightness: u16::MAX,
+    kelvin: 3500,
+};
+
+/// Fully saturated green, at full brightness.
+pub const GREEN: HSBK = HSBK { hue: 21845, ..RED };
+
+/// Fully saturated blue, at full brightness.
+pub const BLUE: HSBK = HSBK { hue: 43691, ..RED };
+
+/// A warm white (2700K) at full brightness, like an incandescent bulb.
+pub const WHITE_WARM: HSBK = HSBK {
+    hue: 0,
+    saturation: 0,
+    brightness: u16::MAX,
+    kelvin: 2700,
+};
+
+/// A cool white (6500K) at full brightness, like daylight.
+pub const WHITE_COOL: HSBK = HSBK {
+    kelvin: 6500,
+    ..WHITE_WARM
+};
+
+/// Builds an [`HSBK`] from human-friendly units.
+///
+/// Starts out as full-brightness white at 3500K.  Out of range values are clamped, except hue,
+/// which wraps around the color wheel.
+///
+/// ```
+/// use lifx::color::HsbkBuilder;
+///
+/// let teal = HsbkBuilder::new()
+///     .hue_degrees(180.0)
+///     .saturation_percent(100.0)
+///     .brightness_percent(50.0)
+///     .build();
+/// assert_eq!(teal.hue, 32768);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HsbkBuilder {
+    hue: u16,
+    saturation: u16,
+    brightness: u16,
+    kelvin: u16,
+}
+
+/// Scales `fraction` (0 to 1) onto the full range of a u16.
+fn scale(fraction: f32) -> u16 {
+    (fraction.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16
+}
+
+impl HsbkBuilder {
+    pub fn new() -> HsbkBuilder {
+        HsbkBuilder {
+            hue: 0,
+            saturation: 0,
+            brightness: u16::MAX,
+            kelvin: 3500,
+        }
+    }
+
+    /// Sets the hue, in degrees around the color wheel.
+    pub fn hue_degrees(mut self, degrees: f32) -> HsbkBuilder {
+        let degrees = degrees.rem_euclid(360.0);
+        // 360 degrees would round up to 65536, which is 0 degrees anyway
+        self.hue = ((degrees / 360.0 * 65536.0).round() as u32 % 65536) as u16;
+        self
+    }
+
+    /// Sets the saturation, from 0 (white) to 100 (fully saturated).
+    pub fn saturation_percent(mut self, percent: f32) -> HsbkBuilder {
+        self.saturation = scale(percent / 100.0);
+        self
+    }
+
+    /// Sets the brightness, from 0 to 100.
+    pub fn brightness_percent(mut self, percent: f32) -> HsbkBuilder {
+        self.brightness = scale(percent / 100.0);
+        self
+    }
+
+    /// Sets the color temperature in kelvin, clamped to 1500-9000.
+    ///
+    /// Only has a visible effect when saturation is low.
+    pub fn kelvin(mut self, kelvin: u16) -> HsbkBuilder {
+        self.kelvin = kelvin.clamp(1500, 9000);
+        self
+    }
+
+    /// Starts from a color written as hex RGB, such as `"#ff8800"`.  The `#` is optional.
+    ///
+    /// Fails with [`LifxError::InvalidColor`] unless there are exactly six hex digits.  See
+    /// [`HsbkBuilder::from`] for how RGB is converted.
+    ///
+    /// ```
+    /// use lifx::color::{HsbkBuilder, RED};
+    ///
+    /// assert_eq!(HsbkBuilder::from_hex("#ff0000").unwrap().build(), RED);
+    /// assert!(HsbkBuilder::from_hex("#ff00").is_err());
+    /// ```
+    pub fn from_hex(hex: &str) -> Result<HsbkBuilder, LifxError> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        // from_str_radix would also accept a sign
+        if digits.len() != 6 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(LifxError::InvalidColor(hex.to_owned()));
+        }
+        let channel = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16).unwrap_or_default();
+        Ok(HsbkBuilder::from((channel(0), channel(2), channel(4))))
+    }
+
+    pub fn build(self) -> HSBK {
+        HSBK {
+            hue: self.hue,
+            saturation: self.saturation,
+            brightness: self.brightness,
+            kelvin: self.kelvin,
+        }
+    }
+}
+
+impl Default for HsbkBuilder {
+    fn default() -> HsbkBuilder {
+        HsbkBuilder::new()
+    }
+}
+
+impl From<(u8, u8, u8)> for HsbkBuilder {
+    /// Starts from an 8-bit RGB color.
+    ///
+    /// Hue, saturation and brightness come from the color's HSV form, and kelvin is left at
+    /// 3500.  Bulbs tint whites by their kelvin, so greys come out slightly warm.
+    fn from((red, green, blue): (u8, u8, u8)) -> HsbkBuilder {
+        let max = red.max(green).max(blue);
+        let min = red.min(green).min(blue);
+        let chroma = (max - min) as f32;
+        let [r, g, b] = [red, green, blue].map(f32::from);
+        let degrees = if chroma == 0.0 {
+            0.0
+        } else if max == red {
+            60.0 * ((g - b) / chroma)
+        } else if max == green {
+            60.0 * ((b - r) / chroma + 2.0)
+        } else {
+            60.0 * ((r - g) / chroma + 4.0)
+        };
+        let saturation = if max == 0 { 0.0 } else { chroma / max as f32 };
+        HsbkBuilder::new()
+            .hue_degrees(degrees)
+            .saturation_percent(saturation * 100.0)
+            .brightness_percent(max as f32 / 255.0 * 100.0)
+    }
+}
+
+/// Linearly interpolates `steps` colors from `start` to `end`, inclusive.
+///
+/// With `shortest_hue`, hue takes the shorter way around the color wheel (so red to magenta
+/// doesn't pass through green); otherwise it moves directly between the two values.
+pub fn gradient(start: HSBK, end: HSBK, steps: usize, shortest_hue: bool) -> Vec<HSBK> {
+    let lerp = |a: u16, b: u16, t: f64| (a as f64 + (b as f64 - a as f64) * t).round() as u16;
+    let mut hue_delta = end.hue as i32 - start.hue as i32;
+    if shortest_hue {
+        if hue_delta > 32768 {
+            hue_delta -= 65536;
+        } else if hue_delta < -32768 {
+            hue_delta += 65536;
+        }
+    }
+    (0..steps)
+        .map(|i| {
+            let t = if steps > 1 {
+                i as f64 / (steps - 1) as f64
+            } else {
+                0.0
+            };
+            let hue = (start.hue as f64 + hue_delta as f64 * t).round() as i32;
+            HSBK {
+                hue: hue.rem_euclid(65536) as u16,
+                saturation: lerp(start.saturation, end.saturation, t),
+                brightness: lerp(start.brightness, end.brightness, t),
+                kelvin: lerp(start.kelvin, end.kelvin, t),
+            }
+        })
+        .collect()
+}
+
+/// Colors less saturated than this, out of 65535, are treated as white.
+const WHITE_SATURATION: u16 = 6554;
+
+/// A rough bucket of white color temperatures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum WhitePreset {
+    /// Up to 2500K.
+    Candle,
+    /// 2501K to 3200K.
+    Warm,
+    /// 3201K to 4500K.
+    Neutral,
+    /// 4501K to 5500K.
+    Cool,
+    /// Above 5500K.
+    Daylight,
+}
+
+impl WhitePreset {
+    /// The bucket `kelvin` falls into.
+    pub fn from_kelvin(kelvin: u16) -> WhitePreset {
+        match kelvin {
+            0..=2500 => WhitePreset::Candle,
+            2501..=3200 => WhitePreset::Warm,
+            3201..=4500 => WhitePreset::Neutral,
+            4501..=5500 => WhitePreset::Cool,
+            _ => WhitePreset::Daylight,
+        }
+    }
+
+    /// The bucket `color` falls into, or `None` if it's saturated enough (10% or more) to be a
+    /// color rather than a white.
+    pub fn of(color: HSBK) -> Option<WhitePreset> {
+        (color.saturation < WHITE_SATURATION).then(|| WhitePreset::from_kelvin(color.kelvin))
+    }
+}
+
+/// Approximates the color of a black body at `kelvin`, with each channel from 0 to 1.
+///
+/// Uses Tanner Helland's curve fit, which is close enough for displaying a bulb's color on
+/// screen.  Zero is treated as pure white.
+fn kelvin_to_rgb(kelvin: u16) -> [f32; 3] {
+    if kelvin == 0 {
+        return [1.0, 1.0, 1.0];
+    }
+    let temp = kelvin as f32 / 100.0;
+    let red = if temp <= 66.0 {
+        255.0
+    } else {
+        329.69873 * (temp - 60.0).powf(-0.13320476)
+    };
+    let green = if temp <= 66.0 {
+        99.4708 * temp.ln() - 161.11957
+    } else {
+        288.12216 * (temp - 60.0).powf(-0.075514846)
+    };
+    let blue = if temp >= 66.0 {
+        255.0
+    } else if temp <= 19.0 {
+        0.0
+    } else {
+        138.51773 * (temp - 10.0).ln() - 305.0448
+    };
+    [red, green, blue].map(|channel| channel.clamp(0.0, 255.0) / 255.0)
+}
+
+/// Converts `color` to 8-bit RGB, for showing it on screen.
+///
+/// The unsaturated part of the color is tinted by its kelvin, the way a bulb shows it, so
+/// unsaturated colors come out as warm or cool whites rather than pure grey.
+///
+/// ```
+/// use lifx::color::{hsbk_to_rgb, HsbkBuilder};
+///
+/// let red = HsbkBuilder::new().saturation_percent(100.0).build();
+/// assert_eq!(hsbk_to_rgb(red), [255, 0, 0]);
+/// ```
+pub fn hsbk_to_rgb(color: HSBK) -> [u8; 3] {
+    let hue = color.hue as f32 / 65536.0 * 6.0;
+    let saturation = color.saturation as f32 / u16::MAX as f32;
+    let value = color.brightness as f32 / u16::MAX as f32;
+
+    // the fully saturated color at this hue
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let pure = match hue as u8 {
+        0 => [1.0, x, 0.0],
+        1 => [x, 1.0, 0.0],
+        2 => [0.0, 1.0, x],
+        3 => [0.0, x, 1.0],
+        4 => [x, 0.0, 1.0],
+        _ => [1.0, 0.0, x],
+    };
+    let white = kelvin_to_rgb(color.kelvin);
+    let mut rgb = [0; 3];
+    for (channel, (pure, white)) in rgb.iter_mut().zip(pure.into_iter().zip(white)) {
+        let mixed = value * (saturation * pure + (1.0 - saturation) * white);
+        *channel = (mixed * 255.0).round() as u8;
+    }
+    rgb
+}
+
+/// A color broken down into human-friendly units, with a rough English name.
+///
+/// The name doesn't depend on the locale, so it's safe to match on.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ColorDescription {
+    /// Degrees around the color wheel, from 0 up to (but not including) 360.
+    pub hue_degrees: f32,
+    /// From 0 (white) to 100 (fully saturated).
+    pub saturation: f32,
+    /// From 0 to 100.
+    pub brightness: f32,
+    pub kelvin: u16,
+    /// A name such as "warm white", "cyan" or "pale orange".
+    pub name: String,
+}
+
+impl ColorDescription {
+    pub fn new(color: HSBK) -> ColorDescription {
+        let hue_degrees = color.hue as f32 / 65536.0 * 360.0;
+        let saturation = color.saturation as f32 / u16::MAX as f32 * 100.0;
+        let brightness = color.brightness as f32 / u16::MAX as f32 * 100.0;
+        let name = if color.brightness == 0 {
+            "off".to_owned()
+        } else if color.saturation < WHITE_SATURATION {
+            match color.kelvin {
+                0..=3000 => "warm white",
+                3001..=4500 => "neutral white",
+                _ => "cool white",
+            }
+            .to_owned()
+        } else {
+            let hue = match hue_degrees {
+                h if h < 15.0 => "red",
+                h if h < 45.0 => "orange",
+                h if h < 70.0 => "yellow",
+                h if h < 150.0 => "green",
+                h if h < 200.0 => "cyan",
+                h if h < 255.0 => "blue",
+                h if h < 290.0 => "purple",
+                h if h < 345.0 => "pink",
+                _ => "red",
+            };
+            if saturation < 50.0 {
+                format!("pale {}", hue)
+            } else {
+                hue.to_owned()
+            }
+        };
+        ColorDescription {
+            hue_degrees,
+            saturation,
+            brightness,
+            kelvin: color.kelvin,
+            name,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_rgb() {
+        assert_eq!(HsbkBuilder::from((255, 0, 0)).build(), RED);
+        assert_eq!(HsbkBuilder::from((0, 255, 0)).build(), GREEN);
+        assert_eq!(HsbkBuilder::from((0, 0, 255)).build(), BLUE);
+
+        let orange = HsbkBuilder::from_hex("FF8800").unwrap().build();
+        assert_eq!(hsbk_to_rgb(orange), [255, 136, 0]);
+        let grey = HsbkBuilder::from((128, 128, 128)).build();
+        assert_eq!(grey.saturation, 0);
+        assert_eq!(grey.brightness, 32896);
+
+        for bad in ["", "#", "#ff880", "#ff88000", "#ff88zz", "#+f8800"] {
+            assert!(HsbkBuilder::from_hex(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_hue_degrees() {
+        assert_eq!(HsbkBuilder::new().hue_degrees(0.0).build().hue, 0);
+        assert_eq!(HsbkBuilder::new().hue_degrees(180.0).build().hue, 32768);
+        assert_eq!(HsbkBuilder::new().hue_degrees(360.0).build().hue, 0);
+        assert_eq!(HsbkBuilder::new().hue_degrees(-90.0).build().hue, 49152);
+    }
+
+    #[test]
+    fn test_percentages() {
+        let color = HsbkBuilder::new()
+            .saturation_percent(100.0)
+            .brightness_percent(100.0)
+            .build();
+        assert_eq!(color.saturation, 65535);
+        assert_eq!(color.brightness, 65535);
+
+        let color = HsbkBuilder::new()
+            .saturation_percent(-5.0)
+            .brightness_percent(250.0)
+            .build();
+        assert_eq!(color.saturation, 0);
+        assert_eq!(color.brightness, 65535);
+    }
+
+    #[test]
+    fn test_gradient() {
+        let red = HsbkBuilder::new().saturation_percent(100.0).build();
+        let magenta = HSBK { hue: 54000, ..red };
+
+        let direct = gradient(red, magenta, 3, false);
+        assert_eq!(direct.len(), 3);
+        assert_eq!(direct[0], red);
+        assert_eq!(direct[1].hue, 27000);
+        assert_eq!(direct[2], magenta);
+
+        // the short way from red to magenta goes backwards, past 0
+        let shortest = gradient(red, magenta, 3, true);
+        assert_eq!(shortest[1].hue, 59768);
+        assert_eq!(shortest[2], magenta);
+
+        assert_eq!(gradient(red, magenta, 1, true), vec![red]);
+        assert!(gradient(red, magenta, 0, true).is_empty());
+    }
+
+    #[test]
+    fn test_describe() {
+        let cyan = HsbkBuilder::new()
+            .hue_degrees(180.0)
+            .saturation_percent(100.0)
+            .build();
+        let description = ColorDescription::new(cyan);
+        assert_eq!(description.name, "cyan");
+        assert_eq!(description.hue_degrees, 180.0);
+        assert_eq!(description.saturation, 100.0);
+        assert_eq!(description.brightness, 100.0);
+
+        let pale_red = HsbkBuilder::new()
+            .hue_degrees(355.0)
+            .saturation_percent(30.0)
+            .build();
+        assert_eq!(ColorDescription::new(pale_red).name, "pale red");
+
+        let warm = HsbkBuilder::new().kelvin(2700).build();
+        assert_eq!(ColorDescription::new(warm).name, "warm white");
+        let cool = HsbkBuilder::new().kelvin(6500).build();
+        assert_eq!(ColorDescription::new(cool).name, "cool white");
+
+        let off = HsbkBuilder::new().brightness_percent(0.0).build();
+        assert_eq!(ColorDescription::new(off).name, "off");
+    }
+
+    #[test]
+    fn test_hsbk_to_rgb() {
+        let full = HsbkBuilder::new().saturation_percent(100.0);
+        assert_eq!(hsbk_to_rgb(full.build()), [255, 0, 0]);
+        assert_eq!(hsbk_to_rgb(full.hue_degrees(120.0).build()), [0, 255, 0]);
+        assert_eq!(hsbk_to_rgb(full.hue_degrees(240.0).build()), [0, 0, 255]);
+        assert_eq!(hsbk_to_rgb(full.hue_degrees(180.0).build()), [0, 255, 255]);
+
+        let off = HsbkBuilder::new().brightness_percent(0.0).build();
+        assert_eq!(hsbk_to_rgb(off), [0, 0, 0]);
+
+        // whites are tinted by their temperature
+        let [red, _, blue] = hsbk_to_rgb(HsbkBuilder::new().kelvin(2700).build());
+        assert!(red > blue);
+        let [red, _, blue] = hsbk_to_rgb(HsbkBuilder::new().kelvin(9000).build());
+        assert!(blue > red);
+    }
+
+    #[test]
+    fn test_white_preset() {
+        assert_eq!(WhitePreset::from_kelvin(2500), WhitePreset::Candle);
+        assert_eq!(WhitePreset::from_kelvin(2700), WhitePreset::Warm);
+        assert_eq!(WhitePreset::from_kelvin(3500), WhitePreset::Neutral);
+        assert_eq!(WhitePreset::from_kelvin(5000), WhitePreset::Cool);
+        assert_eq!(WhitePreset::from_kelvin(6500), WhitePreset::Daylight);
+
+        let white = HsbkBuilder::new().kelvin(2700).saturation_percent(5.0);
+        assert_eq!(WhitePreset::of(white.build()), Some(WhitePreset::Warm));
+        assert_eq!(
+            WhitePreset::of(white.saturation_percent(50.0).build()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_kelvin() {
+        assert_eq!(HsbkBuilder::new().kelvin(2700).build().kelvin, 2700);
+        assert_eq!(HsbkBuilder::new().kelvin(100).build().kelvin, 1500);
+        assert_eq!(HsbkBuilder::new().kelvin(20000).build().kelvin, 9000);
+    }
+}
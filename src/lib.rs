@@ -1,18 +1,237 @@
+#[cfg(feature = "tokio")]
+pub mod async_manager;
+pub mod color;
+pub mod transport;
+
 pub mod bulb_manager {
-    
+
+    use crate::color::{gradient, hsbk_to_rgb, ColorDescription, HsbkBuilder, WhitePreset};
+    use crate::transport::Transport;
     use get_if_addrs::{get_if_addrs, IfAddr, Ifv4Addr};
     use lifx_core::{
-        get_product_info, BuildOptions, Message, PowerLevel, RawMessage, Service, HSBK,
+        get_product_info, BuildOptions, EchoPayload, LifxIdent, LifxString, PowerLevel, RawMessage,
+        TemperatureRange, HSBK,
     };
-    use std::collections::HashMap;
-    use std::ffi::CString;
-    use std::net::{IpAddr, SocketAddr, UdpSocket};
-    
-    use std::sync::{Arc, Mutex};
-    use std::thread::spawn;
-    use std::time::{Duration, Instant};
+    pub use lifx_core::{
+        ApplicationRequest, Message, MultiZoneEffectType, ProductInfo, Service, Waveform,
+    };
+    use log::{debug, error, info, warn};
+    #[cfg(unix)]
+    use socket2::SockRef;
+    use socket2::{Domain, Protocol, Socket, Type};
+    use std::collections::{HashMap, VecDeque};
+    use std::ffi::{CStr, CString};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+
+    #[cfg(feature = "serde")]
+    use std::fs::File;
+    use std::io;
+    #[cfg(feature = "serde")]
+    use std::io::{BufReader, BufWriter};
+    #[cfg(unix)]
+    use std::mem::MaybeUninit;
+    use std::panic::{self, AssertUnwindSafe};
+    #[cfg(feature = "serde")]
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
+    use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender};
+    use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+    use std::thread::{sleep, spawn, JoinHandle};
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+    use thiserror::Error;
 
     const HOUR: Duration = Duration::from_secs(60 * 60);
+    const WORKER_ERROR_BACKLOG: usize = 64;
+    /// LIFX recommends sending no more than 20 messages per second to a single device.
+    pub(crate) const DEFAULT_RATE_LIMIT: u32 = 20;
+    const UNICAST_DISCOVERY_ATTEMPTS: usize = 3;
+    const UNICAST_DISCOVERY_RETRY_DELAY: Duration = Duration::from_millis(250);
+    /// A bulb we haven't heard from in this long is sent a `GetService` to check it's still there.
+    const QUIET_AFTER: Duration = Duration::from_secs(30);
+    /// The delay between the first two checks on a quiet bulb, doubled after each check.
+    const INITIAL_PROBE_BACKOFF: Duration = Duration::from_secs(1);
+    /// Once the delay between checks reaches this, an unanswered check marks the bulb offline.
+    const MAX_PROBE_BACKOFF: Duration = Duration::from_secs(60);
+    /// A bulb's primary address is only replaced once it has gone this long without a reply.
+    const ADDRESS_STICKINESS: Duration = Duration::from_secs(10);
+    /// How often queued commands are retried when coalescing is enabled.
+    const COALESCE_FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+    /// How often the worker looks for quiet bulbs.
+    const PROBE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+    /// How often [`Manager::wait_for_color`] re-requests whatever it's still waiting on.
+    const WAIT_FOR_COLOR_REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+    /// How far each channel of a reported color can be from the one that was set, for
+    /// [`Manager::set_color_verified`].  About 1%.
+    const VERIFY_TOLERANCE: u16 = 656;
+    /// The most datagrams the worker applies under a single lock of the bulb table.
+    const WORKER_BATCH_SIZE: usize = 32;
+    /// The longest label a bulb can be given, in bytes.  Labels are sent as 32 bytes, and we
+    /// always leave room for a terminating nul.
+    const MAX_LABEL_LEN: usize = 31;
+    /// Every LIFX Switch has this many relays, though product info doesn't say so.
+    const SWITCH_RELAYS: u8 = 4;
+    /// How long the worker waits for data before checking whether it should exit, by default.
+    const DEFAULT_RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Errors returned by this crate.
+    #[derive(Error, Debug)]
+    pub enum LifxError {
+        #[error("i/o error")]
+        Io(#[from] io::Error),
+
+        #[error("protocol error")]
+        Protocol(#[from] lifx_core::Error),
+
+        /// The bulb hasn't reported any zone information (yet).
+        #[error("no zone information available")]
+        NoZones,
+
+        /// A zone update was given an empty list of colors.
+        #[error("no colors given")]
+        NoColors,
+
+        /// A zone update would run past the end of the strip.
+        #[error("requested zones up to {requested}, but the strip only has {available}")]
+        InvalidZoneCount { requested: usize, available: u16 },
+
+        /// Sending would exceed the bulb's message rate limit; see [`Manager::set_rate_limit`].
+        #[error("rate limit exceeded")]
+        RateLimited,
+
+        /// The socket couldn't be bound because another process is using the address.
+        #[error("address {0} is already in use")]
+        AddrInUse(SocketAddr),
+
+        /// No bulb with the given target is known to the [`Manager`].
+        #[error("no bulb with target {0:0>16X}")]
+        BulbNotFound(u64),
+
+        /// The bulb table lock was poisoned by a panicking thread.
+        #[error("bulb table lock was poisoned")]
+        LockPoisoned,
+
+        /// The bulb's product doesn't support the requested feature.
+        #[error("not supported by this bulb")]
+        Unsupported,
+
+        /// Nothing answered at the given address before the timeout.
+        #[error("no response from {0}")]
+        NoResponse(SocketAddr),
+
+        /// A label was longer than a bulb can store.
+        #[error("label is {0} bytes long, but bulbs only store {MAX_LABEL_LEN}")]
+        LabelTooLong(usize),
+
+        /// A label contained a nul byte.
+        #[error("label contains a nul byte")]
+        LabelContainsNul,
+
+        /// A waveform was asked to change none of the color channels.
+        #[error("no color channels selected")]
+        NoChannels,
+
+        /// The switch doesn't have a relay with the given index.
+        #[error("no relay with index {0}")]
+        NoSuchRelay(u8),
+
+        /// The bulb replied that it doesn't support messages of this type.
+        #[error("bulb doesn't support message type {0}")]
+        Unhandled(u16),
+
+        /// The socket sent only part of a packet, so the bulb would have ignored it.
+        #[error("only sent {sent} of {expected} bytes")]
+        ShortWrite { sent: usize, expected: usize },
+
+        /// A color couldn't be parsed, such as a malformed hex string.
+        #[error("invalid color {0:?}")]
+        InvalidColor(String),
+
+        /// The bulb didn't report the color it was given before the timeout.
+        #[error("bulb {0:0>16X} didn't confirm the change")]
+        VerifyFailed(u64),
+
+        /// A saved bulb table couldn't be read or written.
+        #[cfg(feature = "serde")]
+        #[error("invalid bulb table")]
+        Json(#[from] serde_json::Error),
+    }
+
+    impl<T> From<PoisonError<T>> for LifxError {
+        fn from(_: PoisonError<T>) -> LifxError {
+            LifxError::LockPoisoned
+        }
+    }
+
+    /// Errors reported by the background worker thread.
+    ///
+    /// These are delivered over the channel returned by [`Manager::errors`].
+    #[derive(Error, Debug)]
+    pub enum WorkerError {
+        /// Receiving from the socket failed.
+        #[error("recv_from failed: {0}")]
+        Recv(io::Error),
+
+        /// A datagram was received but could not be decoded.
+        #[error("error unpacking message from {addr}: {error}")]
+        Unpack {
+            addr: SocketAddr,
+            error: lifx_core::Error,
+        },
+
+        /// The bulb table lock was poisoned by a panicking thread.
+        #[error("bulb table lock was poisoned")]
+        LockPoisoned,
+
+        /// A message was never acknowledged, even after being retransmitted.
+        #[error("no acknowledgement from {target:0>16X} for sequence {seq}")]
+        NoAck { target: u64, seq: u8 },
+
+        /// Retransmitting an unacknowledged message failed.
+        #[error("retransmit failed: {0}")]
+        Retransmit(LifxError),
+
+        /// Checking on a bulb that has gone quiet failed.
+        #[error("checking on {target:0>16X} failed: {error}")]
+        Probe { target: u64, error: LifxError },
+
+        /// Sending a command held back by coalescing failed.
+        #[error("sending a queued command to {target:0>16X} failed: {error}")]
+        Flush { target: u64, error: LifxError },
+
+        /// Handling a message from a bulb panicked.  The message was dropped, and the bulb's
+        /// cached state may be incomplete until it's next refreshed.
+        #[error("handling a message from {target:0>16X} panicked")]
+        HandlerPanicked { target: u64 },
+
+        /// Discovery started by [`Manager::start_periodic_discovery`] failed.
+        #[error("periodic discovery failed: {0}")]
+        Discovery(LifxError),
+
+        /// A bulb rejected a message we sent it, usually with [`LifxError::Unhandled`].
+        #[error("{target:0>16X} rejected a message: {error}")]
+        Rejected { target: u64, error: LifxError },
+
+        /// Sending a step of an animation started by [`Manager::animate_hue`] failed.  The
+        /// animation carries on with the next step.
+        #[error("animating {target:0>16X} failed: {error}")]
+        Animation { target: u64, error: LifxError },
+    }
+
+    impl WorkerError {
+        /// Whether this error means the worker can no longer receive data.
+        pub(crate) fn is_fatal(&self) -> bool {
+            match self {
+                WorkerError::Recv(e) => matches!(
+                    e.kind(),
+                    io::ErrorKind::NotConnected
+                        | io::ErrorKind::BrokenPipe
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::UnexpectedEof
+                ),
+                _ => false,
+            }
+        }
+    }
 
     #[derive(Debug)]
     pub struct RefreshableData<T> {
@@ -35,18 +254,318 @@ pub mod bulb_manager {
             self.data = Some(data);
             self.last_updated = Instant::now()
         }
-        fn needs_refresh(&self) -> bool {
+        /// Whether the data is missing, or older than `max_age`.
+        pub fn is_stale(&self) -> bool {
             self.data.is_none() || self.last_updated.elapsed() > self.max_age
         }
+        /// How long ago the data was fetched, or `None` if it never has been.
+        pub fn age(&self) -> Option<Duration> {
+            self.data.as_ref().map(|_| self.last_updated.elapsed())
+        }
         fn as_ref(&self) -> Option<&T> {
             self.data.as_ref()
         }
     }
+    /// A change to a bulb's state, delivered to receivers returned by [`Manager::subscribe`].
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum BulbEvent {
+        /// We heard from a bulb for the first time.
+        Discovered(u64),
+        /// A bulb's power level changed.
+        PowerChanged { target: u64, level: u16 },
+        /// A bulb's color, or the color of any of its zones, changed.
+        ColorChanged(u64),
+        /// A bulb was forgotten, either after not being heard from for too long or by
+        /// [`Manager::remove_bulb`].
+        LostContact(u64),
+    }
+
+    /// The set of channels that [`BulbEvent`]s are broadcast to.
+    #[derive(Debug, Clone, Default)]
+    pub(crate) struct Subscribers(Arc<Mutex<Vec<Sender<BulbEvent>>>>);
+
+    impl Subscribers {
+        pub(crate) fn subscribe(&self) -> Receiver<BulbEvent> {
+            let (tx, rx) = channel();
+            let mut senders = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+            senders.push(tx);
+            rx
+        }
+
+        /// Sends `event` to every subscriber, forgetting the ones that have hung up.
+        fn emit(&self, event: BulbEvent) {
+            let mut senders = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+            senders.retain(|tx| tx.send(event.clone()).is_ok());
+        }
+    }
+
+    /// A callback run by the worker for every message it receives.  See
+    /// [`Manager::on_raw_message`].
+    pub type RawMessageCallback = Box<dyn Fn(&RawMessage, SocketAddr) + Send>;
+
+    /// The callback registered with [`Manager::on_raw_message`], if any.
+    #[derive(Clone, Default)]
+    pub(crate) struct RawMessageHook(Arc<Mutex<Option<RawMessageCallback>>>);
+
+    impl RawMessageHook {
+        pub(crate) fn set(&self, callback: RawMessageCallback) {
+            *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(callback);
+        }
+
+        pub(crate) fn call(&self, raw: &RawMessage, addr: SocketAddr) {
+            if let Some(callback) = &*self.0.lock().unwrap_or_else(PoisonError::into_inner) {
+                callback(raw, addr);
+            }
+        }
+    }
+
+    impl std::fmt::Debug for RawMessageHook {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let set = self
+                .0
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .is_some();
+            f.debug_tuple("RawMessageHook").field(&set).finish()
+        }
+    }
+
+    /// State that a manager shares with its background worker.
+    #[derive(Debug, Clone)]
+    pub(crate) struct SharedState {
+        pub(crate) source: u32,
+        pub(crate) rate_limit: Arc<AtomicU32>,
+        pub(crate) subscribers: Subscribers,
+        pub(crate) raw_hook: RawMessageHook,
+        /// Whether rate limited color and power commands are queued; see
+        /// [`Manager::enable_coalescing`].
+        pub(crate) coalesce: Arc<AtomicBool>,
+        /// How long each part of a new bulb's cache stays fresh.
+        pub(crate) max_ages: MaxAges,
+        /// How many sent messages each bulb remembers; see [`Manager::set_command_log_len`].
+        pub(crate) command_log_len: Arc<AtomicUsize>,
+        /// See [`ManagerConfig::bulb_port`].
+        pub(crate) bulb_port: Option<u16>,
+    }
+
+    impl SharedState {
+        /// The state for a manager created with `config`.
+        pub(crate) fn from_config(config: &ManagerConfig) -> SharedState {
+            SharedState {
+                max_ages: config.max_ages,
+                bulb_port: config.bulb_port,
+                ..SharedState::new(config.source)
+            }
+        }
+
+        pub(crate) fn new(source: u32) -> SharedState {
+            SharedState {
+                source,
+                rate_limit: Arc::new(AtomicU32::new(DEFAULT_RATE_LIMIT)),
+                subscribers: Subscribers::default(),
+                raw_hook: RawMessageHook::default(),
+                coalesce: Arc::new(AtomicBool::new(false)),
+                max_ages: MaxAges::default(),
+                command_log_len: Arc::new(AtomicUsize::new(0)),
+                bulb_port: None,
+            }
+        }
+    }
+
+    /// How long each kind of cached bulb state is used before [`Manager::refresh`] asks for it
+    /// again.  See [`ManagerConfig::max_ages`].
+    ///
+    /// Shorter ages keep the cache closer to the truth at the cost of more traffic.  Every stale
+    /// value is one more message per refresh, and bulbs are rate limited to 20 messages a second
+    /// by default, so ages much under a few seconds mostly just trip the rate limit.  Ages longer
+    /// than a few minutes for power and color will miss changes made from the LIFX app.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MaxAges {
+        /// The label, model, location, group and firmware versions, which rarely change.
+        /// Defaults to an hour.
+        pub info: Duration,
+        /// The wifi signal strength.  Defaults to a minute.
+        pub wifi_signal: Duration,
+        /// The power level, along with relays, infrared and HEV cycles.  Defaults to 15 seconds.
+        pub power: Duration,
+        /// The color, or the zones of a strip or pixels of a chain.  Defaults to 15 seconds.
+        pub color: Duration,
+    }
+
+    impl Default for MaxAges {
+        fn default() -> MaxAges {
+            MaxAges {
+                info: HOUR,
+                wifi_signal: Duration::from_secs(60),
+                power: Duration::from_secs(15),
+                color: Duration::from_secs(15),
+            }
+        }
+    }
+
+    /// A token bucket that keeps the messages sent to a single bulb under a rate limit.
+    #[derive(Debug)]
+    struct RateLimiter {
+        /// Messages per second, shared with the owning manager.  Zero disables limiting.
+        rate: Arc<AtomicU32>,
+        /// Available tokens, and when they were last topped up.
+        bucket: Mutex<(f64, Instant)>,
+    }
+
+    impl RateLimiter {
+        fn new(rate: Arc<AtomicU32>) -> RateLimiter {
+            let tokens = rate.load(Ordering::Relaxed) as f64;
+            RateLimiter {
+                rate,
+                bucket: Mutex::new((tokens, Instant::now())),
+            }
+        }
+
+        fn try_acquire(&self) -> Result<(), LifxError> {
+            let rate = self.rate.load(Ordering::Relaxed);
+            if rate == 0 {
+                return Ok(());
+            }
+            let capacity = rate as f64;
+            let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+            let (tokens, last_refill) = &mut *bucket;
+            *tokens = (*tokens + last_refill.elapsed().as_secs_f64() * capacity).min(capacity);
+            *last_refill = Instant::now();
+            if *tokens < 1.0 {
+                return Err(LifxError::RateLimited);
+            }
+            *tokens -= 1.0;
+            Ok(())
+        }
+    }
+
+    /// The kinds of command that replace each other when coalescing is enabled.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum CoalesceKind {
+        Color,
+        Power,
+    }
+
+    impl CoalesceKind {
+        fn of(payload: &Message) -> Option<CoalesceKind> {
+            match payload {
+                Message::LightSetColor { .. } => Some(CoalesceKind::Color),
+                Message::SetPower { .. } | Message::LightSetPower { .. } => {
+                    Some(CoalesceKind::Power)
+                }
+                _ => None,
+            }
+        }
+    }
+
+    /// A message that was sent with `ack_required` and hasn't been acknowledged yet.
+    #[derive(Debug)]
+    struct PendingAck {
+        packet: Vec<u8>,
+        sent_at: Instant,
+        /// How many times the message has been sent, including the first time.
+        attempts: u8,
+        /// Whether the message sets the colors of a strip's zones.
+        zones: bool,
+    }
+
+    /// Whether a message asks the bulb to acknowledge it.
+    ///
+    /// Acknowledged messages are resent by the manager's worker until the bulb confirms them (see
+    /// [`RetryPolicy`]), at the cost of an extra reply for every message.  Unacknowledged messages
+    /// halve the traffic but are simply lost if a datagram is dropped, which is fine for things
+    /// like animation frames that are about to be replaced anyway.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum AckMode {
+        /// Ask for an acknowledgement and a response, and retransmit until acknowledged.
+        #[default]
+        Acked,
+        /// Send once, without asking the bulb to reply.
+        Unacked,
+    }
+
+    /// Which way a [`MultiZoneEffectType::Move`] effect scrolls along a strip.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum Direction {
+        /// Away from the end the strip is powered from.
+        #[default]
+        Right,
+        /// Towards the end the strip is powered from.
+        Left,
+    }
+
+    /// Where a bulb's cached [`BulbInfo::name`] came from.  See [`BulbInfo::name_source`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum LabelSource {
+        /// A [`Message::StateLabel`] reply, which is treated as authoritative.
+        StateLabel,
+        /// The label in a [`Message::LightState`].  This is only used until the bulb sends a
+        /// `StateLabel`, since a `LightState` sent before a rename can arrive after it.
+        LightState,
+        /// A rename with [`BulbInfo::set_label`], before the bulb has confirmed it.
+        SetLabel,
+        /// A label loaded with `Manager::load_known_bulbs`.
+        Saved,
+    }
+
+    #[derive(PartialEq)]
+    struct ColorSnapshot {
+        single: Option<HSBK>,
+        multi: Option<Vec<Option<HSBK>>>,
+        extended: Option<Vec<HSBK>>,
+        tiles: Option<Vec<Option<Box<[HSBK; 64]>>>>,
+    }
+
+    /// The most colors a single extended multizone message can carry.
+    const EXTENDED_ZONES_PER_MESSAGE: usize = 82;
+    /// The most pixels a single tile message can carry.
+    const TILE_PIXELS_PER_MESSAGE: usize = 64;
+
+    const BLACK: HSBK = HSBK {
+        hue: 0,
+        saturation: 0,
+        brightness: 0,
+        kelvin: 0,
+    };
+
     pub struct Zones {
         pub zones_count: u16,
+        /// One color per zone.  Zones the strip hasn't reported yet are black.
+        colors: Vec<HSBK>,
+        /// The first zone in the most recent report.
         zone_index: u16,
+        /// How many zones the most recent report covered.
         colors_count: u8,
-        colors: Box<[HSBK; 82]>,
+    }
+
+    impl Zones {
+        /// The index of the first zone in the strip's most recent
+        /// [`Message::StateExtendedColorZones`].
+        ///
+        /// Long strips report their zones 82 at a time, so this is where the last chunk started.
+        pub fn zone_index(&self) -> u16 {
+            self.zone_index
+        }
+
+        /// How many zones the strip's most recent [`Message::StateExtendedColorZones`] covered.
+        pub fn colors_count(&self) -> u8 {
+            self.colors_count
+        }
+
+        /// Copies `colors` into place starting at `zone_index`, resizing if the zone count changed.
+        fn merge(&mut self, zones_count: u16, zone_index: u16, colors: &[HSBK]) {
+            self.zone_index = zone_index;
+            self.colors_count = colors.len() as u8;
+            if zones_count != self.zones_count {
+                // the old colors don't belong to any of the new zones
+                self.colors.clear();
+                self.zones_count = zones_count;
+            }
+            self.colors.resize(zones_count as usize, BLACK);
+            let start = (zone_index as usize).min(self.colors.len());
+            let end = (start + colors.len()).min(self.colors.len());
+            self.colors[start..end].copy_from_slice(&colors[..end - start]);
+        }
     }
     pub struct BulbInfo {
         pub last_seen: Instant,
@@ -54,12 +573,60 @@ pub mod bulb_manager {
         pub addr: SocketAddr,
         pub name: RefreshableData<CString>,
         pub model: RefreshableData<(u32, u32)>,
-        pub location: RefreshableData<CString>,
-        pub host_firmware: RefreshableData<(u16, u16)>,
-        pub wifi_firmware: RefreshableData<(u16, u16)>,
+        /// The location label, and when it was last changed (nanoseconds since the epoch).
+        pub location: RefreshableData<(CString, u64)>,
+        /// The ID of the location, reported along with `location`.
+        location_id: Option<[u8; 16]>,
+        /// The group label, and when it was last changed (nanoseconds since the epoch).
+        pub group: RefreshableData<(CString, u64)>,
+        /// The major and minor version, and when the firmware was built (nanoseconds since the
+        /// epoch).
+        pub host_firmware: RefreshableData<(u16, u16, u64)>,
+        /// Like `host_firmware`, but for the wifi module.
+        pub wifi_firmware: RefreshableData<(u16, u16, u64)>,
+        pub wifi_signal: RefreshableData<f32>,
         pub power_level: RefreshableData<u16>,
+        pub infrared: RefreshableData<u16>,
+        /// Seconds left in the current HEV clean cycle, or 0 if none is running.
+        pub hev_cycle: RefreshableData<u32>,
         pub zones: RefreshableData<Zones>,
+        /// The power level of each relay, for switches.  Only refreshed if the device reports
+        /// that it has relays.
+        pub relays: Vec<RefreshableData<u16>>,
         pub color: Color,
+        /// Round trip time of the most recent answered [`BulbInfo::ping`].
+        pub last_rtt: Option<Duration>,
+        /// Each service the bulb has advertised, and the port it's offered on.
+        pub services: Vec<(Service, u32)>,
+        /// When the bulb last acknowledged a message setting its zones.
+        last_zone_ack: Option<Instant>,
+        /// Every address the bulb has replied from in the last hour, and when it last did.
+        addresses: Vec<(SocketAddr, Instant)>,
+        /// The payload of the outstanding ping, and when it was sent.
+        pending_echo: Mutex<Option<([u8; 64], Instant)>>,
+        /// The sequence number of the most recently sent message.
+        sequence: AtomicU8,
+        /// Messages sent with `ack_required` that haven't been acknowledged yet, by sequence.
+        outstanding: Mutex<HashMap<u8, PendingAck>>,
+        limiter: RateLimiter,
+        /// Whether commands that hit the rate limit are queued, shared with the owning manager.
+        coalesce: Arc<AtomicBool>,
+        /// Commands held back by the rate limit, at most one of each kind, oldest first.
+        coalesced: Mutex<Vec<(CoalesceKind, Message, AckMode)>>,
+        /// How many entries `command_log` keeps, shared with the owning manager.
+        command_log_len: Arc<AtomicUsize>,
+        /// When each of the most recent messages was sent, and its type, oldest first.
+        command_log: Mutex<VecDeque<(Instant, &'static str)>>,
+        /// Where `name` was last set from.
+        name_source: Option<LabelSource>,
+        /// How long each part of the cache stays fresh, for data created once the model is known.
+        max_ages: MaxAges,
+        /// If set, overrides the port the bulb advertises.  See [`ManagerConfig::bulb_port`].
+        bulb_port: Option<u16>,
+        online: bool,
+        /// How long to wait after the next check on a quiet bulb before checking again.
+        probe_backoff: Duration,
+        next_probe: Instant,
     }
 
     #[derive(Debug)]
@@ -67,11 +634,28 @@ pub mod bulb_manager {
         Unknown,
         Single(RefreshableData<HSBK>),
         Multi(RefreshableData<Vec<Option<HSBK>>>),
+        /// A chain of matrix devices, such as the LIFX Tile or Candle.
+        Tiles(RefreshableData<Vec<Tile>>),
+    }
+
+    /// One tile in a chain, as reported by [`Message::StateDeviceChain`].
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Tile {
+        /// The number of pixels in each row.
+        pub width: u8,
+        /// The number of rows.
+        pub height: u8,
+        /// Pixel colors, row by row, if the tile has reported them.
+        pub colors: Option<Box<[HSBK; 64]>>,
     }
 
     impl BulbInfo {
-        fn new(source: u32, target: u64, addr: SocketAddr) -> BulbInfo {
-            println!("New bulb at: {:?}", addr);
+        fn new(shared: &SharedState, target: u64, mut addr: SocketAddr) -> BulbInfo {
+            if let Some(port) = shared.bulb_port {
+                addr.set_port(port);
+            }
+            info!("New bulb at: {:?}", addr);
+            let max_ages = shared.max_ages;
             BulbInfo {
                 last_seen: Instant::now(),
                 addr,
@@ -79,474 +663,4391 @@ pub mod bulb_manager {
                     target: Some(target),
                     ack_required: true,
                     res_required: true,
-                    source: source,
+                    source: shared.source,
                     sequence: 0,
                 },
-                name: RefreshableData::empty(HOUR, Message::GetLabel),
-                model: RefreshableData::empty(HOUR, Message::GetVersion),
-                location: RefreshableData::empty(HOUR, Message::GetLocation),
-                host_firmware: RefreshableData::empty(HOUR, Message::GetHostFirmware),
-                wifi_firmware: RefreshableData::empty(HOUR, Message::GetWifiFirmware),
-                power_level: RefreshableData::empty(Duration::from_secs(15), Message::GetPower),
-                zones: RefreshableData::empty(
-                    Duration::from_secs(15),
-                    Message::GetExtendedColorZones,
-                ),
+                name: RefreshableData::empty(max_ages.info, Message::GetLabel),
+                model: RefreshableData::empty(max_ages.info, Message::GetVersion),
+                location: RefreshableData::empty(max_ages.info, Message::GetLocation),
+                location_id: None,
+                group: RefreshableData::empty(max_ages.info, Message::GetGroup),
+                host_firmware: RefreshableData::empty(max_ages.info, Message::GetHostFirmware),
+                wifi_firmware: RefreshableData::empty(max_ages.info, Message::GetWifiFirmware),
+                wifi_signal: RefreshableData::empty(max_ages.wifi_signal, Message::GetWifiInfo),
+                power_level: RefreshableData::empty(max_ages.power, Message::GetPower),
+                infrared: RefreshableData::empty(max_ages.power, Message::LightGetInfrared),
+                hev_cycle: RefreshableData::empty(max_ages.power, Message::LightGetHevCycle),
+                zones: RefreshableData::empty(max_ages.color, Message::GetExtendedColorZones),
+                relays: (0..SWITCH_RELAYS)
+                    .map(|relay_index| {
+                        RefreshableData::empty(
+                            max_ages.power,
+                            Message::RelayGetPower { relay_index },
+                        )
+                    })
+                    .collect(),
                 color: Color::Unknown,
+                last_rtt: None,
+                last_zone_ack: None,
+                services: Vec::new(),
+                addresses: vec![(addr, Instant::now())],
+                pending_echo: Mutex::new(None),
+                sequence: AtomicU8::new(0),
+                outstanding: Mutex::new(HashMap::new()),
+                limiter: RateLimiter::new(shared.rate_limit.clone()),
+                coalesce: shared.coalesce.clone(),
+                coalesced: Mutex::new(Vec::new()),
+                command_log_len: shared.command_log_len.clone(),
+                command_log: Mutex::new(VecDeque::new()),
+                name_source: None,
+                max_ages,
+                bulb_port: shared.bulb_port,
+                online: true,
+                probe_backoff: INITIAL_PROBE_BACKOFF,
+                next_probe: Instant::now(),
+            }
+        }
+        /// The color of every zone, one entry per zone.
+        pub fn get_colors(&self) -> Result<&[HSBK], LifxError> {
+            let zones = self.zones.as_ref().ok_or(LifxError::NoZones)?;
+            Ok(&zones.colors)
+        }
+        pub fn get_length(&self) -> Result<u32, LifxError> {
+            let zones = self.zones.as_ref().ok_or(LifxError::NoZones)?;
+            Ok(zones.zones_count.into())
+        }
+
+        /// The cached color of a single-zone bulb, or `None` if it hasn't been reported yet or
+        /// the bulb isn't a single-zone bulb.
+        pub fn current_color(&self) -> Option<HSBK> {
+            match &self.color {
+                Color::Single(d) => d.as_ref().copied(),
+                _ => None,
             }
         }
-        pub fn get_colors(&self) -> Result<Box<[HSBK; 82]>, failure::Error>{
-            Ok(self.zones.as_ref().unwrap().colors.clone())
+
+        /// Which white the bulb is showing, or `None` if it's showing a color instead.  See
+        /// [`WhitePreset`] for the boundaries between the buckets.
+        ///
+        /// Like [`BulbInfo::current_color`], this is also `None` if the bulb's color hasn't been
+        /// reported yet or it isn't a single-zone bulb.
+        pub fn white_preset(&self) -> Option<WhitePreset> {
+            self.current_color().and_then(WhitePreset::of)
         }
-        pub fn get_length(&self) -> Result<u32, failure::Error>{
-            Ok(self.zones.as_ref().unwrap().zones_count.clone().into())
+
+        /// The cached color of every zone of a strip as RGB, for drawing it on screen.  See
+        /// [`color::hsbk_to_rgb`](crate::color::hsbk_to_rgb).
+        ///
+        /// Zones that haven't been reported yet are black if `fill_unknown` is true, and left out
+        /// otherwise.  This is `None` if the strip hasn't reported any zones yet or the bulb isn't
+        /// a strip.
+        pub fn zones_as_rgb(&self, fill_unknown: bool) -> Option<Vec<[u8; 3]>> {
+            match &self.color {
+                Color::Multi(d) => d.as_ref().map(|zones| {
+                    zones
+                        .iter()
+                        .filter_map(|zone| match zone {
+                            Some(color) => Some(hsbk_to_rgb(*color)),
+                            None if fill_unknown => Some([0, 0, 0]),
+                            None => None,
+                        })
+                        .collect()
+                }),
+                _ => None,
+            }
         }
 
-        fn update(&mut self, addr: SocketAddr) {
-            self.last_seen = Instant::now();
-            self.addr = addr;
+        /// The cached color of a single-zone bulb, broken down for display.  See
+        /// [`BulbInfo::current_color`] for when this is `None`.
+        pub fn describe_color(&self) -> Option<ColorDescription> {
+            self.current_color().map(ColorDescription::new)
         }
 
-        fn refresh_if_needed<T>(
-            &self,
-            sock: &UdpSocket,
-            data: &RefreshableData<T>,
-        ) -> Result<(), failure::Error> {
-            if data.needs_refresh() {
-                let message: RawMessage =
-                    RawMessage::build(&self.options, data.refresh_msg.clone())?;
-                sock.send_to(&message.pack()?, self.addr)?;
+        /// The cached color of every zone of a strip, or `None` if the strip hasn't reported any
+        /// zones yet or the bulb isn't a strip.  Zones that haven't been reported yet are black.
+        pub fn current_zone_colors(&self) -> Option<Vec<HSBK>> {
+            match &self.color {
+                Color::Multi(d) => d
+                    .as_ref()
+                    .map(|zones| zones.iter().map(|zone| zone.unwrap_or(BLACK)).collect()),
+                _ => None,
             }
-            Ok(())
         }
 
-        pub fn toggle_bulb(&self, sock: &UdpSocket) -> Result<(), failure::Error> {
-            let payload: Message;
-            if let Some(level) = self.power_level.as_ref() {
-                if *level > 0 {
-                    payload = Message::SetPower {
-                        level: lifx_core::PowerLevel::Standby,
-                    };
-                } else {
-                    payload = Message::SetPower {
-                        level: lifx_core::PowerLevel::Enabled,
-                    };
+        fn update(&mut self, mut addr: SocketAddr) {
+            let now = Instant::now();
+            self.last_seen = now;
+            // keep sending to the advertised port, even if a reply came from somewhere else
+            if let Some(port) = self.udp_port() {
+                addr.set_port(port);
+            }
+            self.addresses
+                .retain(|(known, seen)| *known == self.addr || now - *seen < HOUR);
+            match self.addresses.iter_mut().find(|(known, _)| *known == addr) {
+                Some((_, seen)) => *seen = now,
+                None => {
+                    info!("Bulb at {} is also reachable at {}", self.addr, addr);
+                    self.addresses.push((addr, now));
                 }
-            } else {
-                payload = Message::SetPower {
-                    level: lifx_core::PowerLevel::Enabled,
-                };
             }
-            let message: RawMessage = RawMessage::build(&self.options, payload)?;
-            sock.send_to(&message.pack()?, self.addr)?;
+            // a bulb reachable over two interfaces replies on both, so only move away from the
+            // current address once it stops answering, rather than flip-flopping between them
+            let primary_seen = self
+                .addresses
+                .iter()
+                .find(|(known, _)| *known == self.addr)
+                .map(|(_, seen)| *seen);
+            if addr != self.addr && primary_seen.is_none_or(|seen| now - seen > ADDRESS_STICKINESS)
+            {
+                info!("Bulb at {} moved to {}", self.addr, addr);
+                self.addr = addr;
+            }
+            if !self.online {
+                info!("Bulb at {} is back online", addr);
+            }
+            self.online = true;
+            self.probe_backoff = INITIAL_PROBE_BACKOFF;
+            self.next_probe = self.last_seen;
+        }
+
+        /// Every address the bulb has replied from recently, starting with the one messages are
+        /// sent to.
+        pub fn addresses(&self) -> Vec<SocketAddr> {
+            let mut addrs = vec![self.addr];
+            addrs.extend(
+                self.addresses
+                    .iter()
+                    .map(|(addr, _)| *addr)
+                    .filter(|addr| *addr != self.addr),
+            );
+            addrs
+        }
+
+        /// The port to send to: [`ManagerConfig::bulb_port`] if it's set, otherwise the UDP port
+        /// the bulb advertised, if it has, and the port is valid.
+        fn udp_port(&self) -> Option<u16> {
+            self.bulb_port.or_else(|| {
+                self.services
+                    .iter()
+                    .find(|(service, _)| *service == Service::UDP)
+                    .and_then(|(_, port)| u16::try_from(*port).ok())
+            })
+        }
+
+        /// Whether the bulb is still answering.
+        ///
+        /// The [`Manager`]'s worker thread checks on bulbs that have gone quiet, waiting longer
+        /// and longer between checks.  A bulb is marked offline once it has ignored checks for a
+        /// couple of minutes, and back online as soon as we hear from it again.
+        pub fn online(&self) -> bool {
+            self.online
+        }
+
+        /// If the bulb has gone quiet and is due a check, sends it `probe` (a `GetService`).
+        fn probe_if_quiet(&mut self, sock: &dyn Transport, probe: &[u8]) -> Result<(), LifxError> {
+            let now = Instant::now();
+            if now.duration_since(self.last_seen) < QUIET_AFTER || now < self.next_probe {
+                return Ok(());
+            }
+            if self.online && self.probe_backoff >= MAX_PROBE_BACKOFF {
+                info!("Bulb at {} has gone offline", self.addr);
+                self.online = false;
+            }
+            debug!("Checking on quiet bulb at {}", self.addr);
+            self.next_probe = now + self.probe_backoff;
+            self.probe_backoff = (self.probe_backoff * 2).min(MAX_PROBE_BACKOFF);
+            send_packet(sock, probe, self.addr)?;
             Ok(())
         }
 
-        pub fn set_power_duration(
+        /// Packs `payload` for this bulb, charging it against the bulb's rate limit.
+        pub(crate) fn build_packet(&self, payload: Message) -> Result<Vec<u8>, LifxError> {
+            self.build_packet_with(&self.options, payload)
+        }
+
+        fn build_packet_with(
             &self,
-            sock: &UdpSocket,
-            level: u16,
-            duration: u32,
-        ) -> Result<(), failure::Error> {
-            let payload: Message = Message::LightSetPower {
-                level: level,
-                duration: duration,
+            options: &BuildOptions,
+            payload: Message,
+        ) -> Result<Vec<u8>, LifxError> {
+            self.limiter.try_acquire()?;
+            let options = BuildOptions {
+                sequence: self.next_sequence(),
+                ..*options
             };
-            let message: RawMessage = RawMessage::build(&self.options, payload)?;
-            sock.send_to(&message.pack()?, self.addr)?;
-            Ok(())
+            let zones = matches!(
+                payload,
+                Message::SetColorZones { .. } | Message::SetExtendedColorZones { .. }
+            );
+            let name = payload.name();
+            let message: RawMessage = RawMessage::build(&options, payload)?;
+            let bytes = message.pack()?;
+            self.log_command(name);
+            if options.ack_required {
+                let mut outstanding = self
+                    .outstanding
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                outstanding.insert(
+                    options.sequence,
+                    PendingAck {
+                        packet: bytes.clone(),
+                        sent_at: Instant::now(),
+                        attempts: 1,
+                        zones,
+                    },
+                );
+            }
+            Ok(bytes)
         }
 
-        pub fn set_power(&self, sock: &UdpSocket, level: PowerLevel) -> Result<(), failure::Error> {
-            let payload: Message = Message::SetPower { level: level };
-            let message: RawMessage = RawMessage::build(&self.options, payload)?;
-            sock.send_to(&message.pack()?, self.addr)?;
-            Ok(())
+        /// Claims the sequence number for the next message sent to this bulb.
+        ///
+        /// Sequence numbers count up from 1 to 255 and then wrap back around to 1.  Zero is never
+        /// used, so that it can't be confused with the sequence of an unsolicited message.  Since
+        /// there are only 255 of them, an unacknowledged message is forgotten once its sequence
+        /// number comes around again.
+        fn next_sequence(&self) -> u8 {
+            let advance = |seq: u8| if seq == u8::MAX { 1 } else { seq + 1 };
+            let prev = self
+                .sequence
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |seq| {
+                    Some(advance(seq))
+                })
+                .unwrap_or_else(|seq| seq);
+            advance(prev)
         }
 
-        pub fn set_bulb_color(
+        /// Resends every message that hasn't been acknowledged within `policy.timeout`.
+        ///
+        /// Messages that have already been sent `policy.retries` extra times are given up on, and
+        /// their sequence numbers returned.
+        fn retransmit_unacknowledged(
             &self,
-            sock: &UdpSocket,
-            color: HSBK,
-            duration: u32,
-        ) -> Result<(), failure::Error> {
-            let payload: Message = Message::LightSetColor {
-                reserved: 0,
-                color: color,
-                duration: duration,
-            };
-            let message: RawMessage = RawMessage::build(&self.options, payload)?;
-            sock.send_to(&message.pack()?, self.addr)?;
-            Ok(())
+            sock: &dyn Transport,
+            policy: &RetryPolicy,
+        ) -> Result<Vec<u8>, LifxError> {
+            let mut outstanding = self
+                .outstanding
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let mut given_up: Vec<u8> = Vec::new();
+            for (seq, pending) in outstanding.iter_mut() {
+                if pending.sent_at.elapsed() < policy.timeout {
+                    continue;
+                }
+                if pending.attempts > policy.retries {
+                    given_up.push(*seq);
+                    continue;
+                }
+                match self.limiter.try_acquire() {
+                    Ok(()) => (),
+                    // try again on the next pass
+                    Err(LifxError::RateLimited) => break,
+                    Err(e) => return Err(e),
+                }
+                send_packet(sock, &pending.packet, self.addr)?;
+                pending.sent_at = Instant::now();
+                pending.attempts += 1;
+            }
+            for seq in &given_up {
+                outstanding.remove(seq);
+            }
+            Ok(given_up)
         }
-        pub fn set_strip_array(
+
+        /// The number of messages sent with `ack_required` that haven't been acknowledged yet.
+        pub fn unacknowledged(&self) -> usize {
+            self.outstanding
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .len()
+        }
+
+        /// The sequence number of the most recently sent message, or 0 if nothing has been sent.
+        ///
+        /// Bulbs echo this back in their acknowledgements, so it can be used to match acks up
+        /// with the messages that caused them.
+        pub fn current_sequence(&self) -> u8 {
+            self.sequence.load(Ordering::Relaxed)
+        }
+
+        fn send(&self, sock: &dyn Transport, payload: Message) -> Result<(), LifxError> {
+            self.send_with_ack(sock, payload, AckMode::Acked)
+        }
+
+        fn send_with_ack(
             &self,
-            sock: &UdpSocket,
-            colors: Box<[HSBK; 82]>,
-            duration: u32,
-        ) -> Result<(), failure::Error> {
-            if let Some(zones) = self.zones.as_ref() {
-                let payload: Message = Message::SetExtendedColorZones {
-                    duration: duration,
-                    apply: lifx_core::ApplicationRequest::Apply,
-                    zone_index: 0,
-                    colors_count: zones.colors_count,
-                    colors: colors,
-                };
-                // println!("{:?}", payload);
-                let message: RawMessage = RawMessage::build(&self.options, payload)?;
-                sock.send_to(&message.pack()?, self.addr)?;
+            sock: &dyn Transport,
+            payload: Message,
+            ack: AckMode,
+        ) -> Result<(), LifxError> {
+            if self.coalesce.load(Ordering::Relaxed) {
+                if let Some(kind) = CoalesceKind::of(&payload) {
+                    let mut queued = self
+                        .coalesced
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner);
+                    // this replaces any older command of the same kind that's still waiting, and
+                    // goes behind any of the other kind, so it can't overtake them
+                    queued.retain(|(known, _, _)| *known != kind);
+                    queued.push((kind, payload, ack));
+                    self.send_queued(sock, &mut queued)?;
+                    return Ok(());
+                }
             }
+            send_packet(sock, &self.pack_with_ack(payload, ack)?, self.addr)?;
             Ok(())
         }
 
-        fn query_for_missing_info(&self, sock: &UdpSocket) -> Result<(), failure::Error> {
-            self.refresh_if_needed(sock, &self.name)?;
-            self.refresh_if_needed(sock, &self.model)?;
-            self.refresh_if_needed(sock, &self.location)?;
-            self.refresh_if_needed(sock, &self.host_firmware)?;
-            self.refresh_if_needed(sock, &self.wifi_firmware)?;
-            self.refresh_if_needed(sock, &self.power_level)?;
-            match &self.color {
-                Color::Unknown => (), // we'll need to wait to get info about this bulb's model, so we'll know if it's multizone or not
-                Color::Single(d) => self.refresh_if_needed(sock, d)?,
-                Color::Multi(d) => self.refresh_if_needed(sock, d)?,
+        fn pack_with_ack(&self, payload: Message, ack: AckMode) -> Result<Vec<u8>, LifxError> {
+            match ack {
+                AckMode::Acked => self.build_packet(payload),
+                AckMode::Unacked => {
+                    let options = BuildOptions {
+                        ack_required: false,
+                        res_required: false,
+                        ..self.options
+                    };
+                    self.build_packet_with(&options, payload)
+                }
             }
-            if let Some((vendor, product)) = self.model.as_ref() {
-                if let Some(info) = get_product_info(*vendor, *product) {
-                    if info.extended {
-                        self.refresh_if_needed(sock, &self.zones)?;
+        }
+
+        /// Sends as many queued commands as the rate limit allows, oldest first.
+        ///
+        /// Returns whether any are still queued.
+        fn flush_coalesced(&self, sock: &dyn Transport) -> Result<bool, LifxError> {
+            let mut queued = self
+                .coalesced
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            self.send_queued(sock, &mut queued)?;
+            Ok(!queued.is_empty())
+        }
+
+        /// Sends commands from the front of `queued` until it's empty or the rate limit is hit.
+        fn send_queued(
+            &self,
+            sock: &dyn Transport,
+            queued: &mut Vec<(CoalesceKind, Message, AckMode)>,
+        ) -> Result<(), LifxError> {
+            while let Some((_, payload, ack)) = queued.first() {
+                let bytes = match self.pack_with_ack(payload.clone(), *ack) {
+                    Err(LifxError::RateLimited) => break,
+                    bytes => {
+                        queued.remove(0);
+                        bytes?
                     }
-                }
+                };
+                send_packet(sock, &bytes, self.addr)?;
             }
             Ok(())
         }
-    }
 
-    impl std::fmt::Debug for BulbInfo {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(
-                f,
-                "BulbInfo({:0>16X} - {}  ",
-                self.options.target.unwrap(),
-                self.addr
+        fn push_if_needed<T>(pending: &mut Vec<Message>, data: &RefreshableData<T>) {
+            if data.is_stale() {
+                pending.push(data.refresh_msg.clone());
+            }
+        }
+
+        /// Whether the bulb is on, or `None` if it hasn't reported its power level yet.
+        ///
+        /// A bulb that is fading in or out counts as on.
+        pub fn is_on(&self) -> Option<bool> {
+            self.power_level.as_ref().map(|level| *level > 0)
+        }
+
+        /// The bulb's power level as a percentage, or `None` if it hasn't been reported yet.
+        ///
+        /// This is only ever between 0 and 100 partway through a fade started with
+        /// [`BulbInfo::set_power_duration`].
+        pub fn power_percent(&self) -> Option<f32> {
+            self.power_level
+                .as_ref()
+                .map(|level| *level as f32 / 655.35)
+        }
+
+        pub fn toggle_bulb(&self, sock: &dyn Transport) -> Result<(), LifxError> {
+            let level = self.toggled_level();
+            self.send(sock, Message::SetPower { level })
+        }
+
+        /// The power level that toggles the bulb: off if it's on, and on otherwise, including
+        /// when its power level hasn't been reported yet.
+        pub(crate) fn toggled_level(&self) -> PowerLevel {
+            PowerLevel::from(self.is_on() != Some(true))
+        }
+
+        /// Like [`BulbInfo::toggle_bulb`], but fades over `duration` milliseconds.
+        ///
+        /// A bulb whose power level hasn't been reported yet is turned on.
+        pub fn toggle_with_duration(
+            &self,
+            sock: &dyn Transport,
+            duration: u32,
+        ) -> Result<(), LifxError> {
+            let level = self.toggled_level();
+            self.set_power_duration(sock, level as u16, duration)
+        }
+
+        pub fn set_power_duration(
+            &self,
+            sock: &dyn Transport,
+            level: u16,
+            duration: u32,
+        ) -> Result<(), LifxError> {
+            let payload: Message = Message::LightSetPower { level, duration };
+            self.send(sock, payload)
+        }
+
+        pub fn set_power(&self, sock: &dyn Transport, level: PowerLevel) -> Result<(), LifxError> {
+            let payload: Message = Message::SetPower { level };
+            self.send(sock, payload)
+        }
+
+        /// Sends an echo request, so the round trip time can be measured once the bulb answers.
+        ///
+        /// Only the most recent ping is tracked; replies to earlier pings are ignored.
+        pub fn ping(&self, sock: &dyn Transport, payload: [u8; 64]) -> Result<(), LifxError> {
+            self.send(
+                sock,
+                Message::EchoRequest {
+                    payload: EchoPayload(payload),
+                },
             )?;
+            let mut pending = self
+                .pending_echo
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            *pending = Some((payload, Instant::now()));
+            Ok(())
+        }
 
-            if let Some(name) = self.name.as_ref() {
-                write!(f, "{}", name.to_string_lossy())?;
-            }
-            if let Some(location) = self.location.as_ref() {
-                write!(f, "/{}", location.to_string_lossy())?;
+        /// The round trip time of the most recent answered ping.
+        pub fn rtt(&self) -> Option<Duration> {
+            self.last_rtt
+        }
+
+        /// The wifi signal strength in approximate dBm, if the bulb has reported it.
+        ///
+        /// Most bulbs report their signal in milliwatts, which converts to a negative dBm value.
+        /// Some older products report a signal-to-noise ratio instead, which comes out positive.
+        pub fn wifi_rssi_dbm(&self) -> Option<f32> {
+            let signal = *self.wifi_signal.as_ref()?;
+            if signal <= 0.0 {
+                return None;
             }
-            if let Some((vendor, product)) = self.model.as_ref() {
-                if let Some(info) = get_product_info(*vendor, *product) {
-                    write!(f, " - {} ", info.name)?;
-                    write!(f, " - {} ", product)?;
-                } else {
-                    write!(
-                        f,
-                        " - Unknown model (vendor={}, product={}) ",
-                        vendor, product
-                    )?;
-                }
+            Some((10.0 * signal.log10()).round())
+        }
+
+        /// A rough 0-100 rating of the wifi signal, suitable for a signal bar.
+        ///
+        /// Follows the thresholds from the LIFX docs: -100 dBm and below is 0, -50 dBm and above is
+        /// 100.  For products that report a signal-to-noise ratio, 4 and below is 0 and 16 and above
+        /// is 100.  A value of 200 means there is no signal.
+        pub fn signal_quality(&self) -> Option<u8> {
+            let rssi = self.wifi_rssi_dbm()?;
+            let quality = if rssi < 0.0 {
+                (rssi + 100.0) * 2.0
+            } else if rssi == 200.0 {
+                0.0
+            } else {
+                (rssi - 4.0) * 100.0 / 12.0
+            };
+            Some(quality.clamp(0.0, 100.0) as u8)
+        }
+
+        /// Reboots the bulb, as if it had been power cycled.
+        ///
+        /// The bulb doesn't reply, so this doesn't ask for an acknowledgement either.  The bulb will
+        /// be unreachable for a few seconds afterwards.
+        pub fn force_reboot(&self, sock: &dyn Transport) -> Result<(), LifxError> {
+            self.send_with_ack(sock, Message::SetReboot, AckMode::Unacked)
+        }
+
+        /// When the bulb's host firmware was built, if the bulb has reported it.
+        pub fn host_firmware_built(&self) -> Option<SystemTime> {
+            let (_, _, build) = self.host_firmware.as_ref()?;
+            Some(UNIX_EPOCH + Duration::from_nanos(*build))
+        }
+
+        /// When the bulb's wifi firmware was built, if the bulb has reported it.
+        pub fn wifi_firmware_built(&self) -> Option<SystemTime> {
+            let (_, _, build) = self.wifi_firmware.as_ref()?;
+            Some(UNIX_EPOCH + Duration::from_nanos(*build))
+        }
+
+        /// The label of the location this bulb belongs to, if the bulb has reported it.
+        pub fn location_label(&self) -> Option<&CStr> {
+            self.location.as_ref().map(|(label, _)| label.as_c_str())
+        }
+
+        /// The 16 byte ID of the location this bulb belongs to, if the bulb has reported it.
+        ///
+        /// Bulbs with the same location ID are in the same location, whatever their labels say.
+        pub fn location_id(&self) -> Option<[u8; 16]> {
+            self.location_id
+        }
+
+        /// The label of the group this bulb belongs to, if the bulb has reported it.
+        pub fn group_label(&self) -> Option<&CStr> {
+            self.group.as_ref().map(|(label, _)| label.as_c_str())
+        }
+
+        /// Renames the bulb, and updates the cached [`BulbInfo::name`] to match straight away.
+        ///
+        /// Fails with [`LifxError::LabelTooLong`] if `label` is more than 31 bytes of UTF-8, or
+        /// [`LifxError::LabelContainsNul`] if it contains a nul byte.
+        pub fn set_label(&mut self, sock: &dyn Transport, label: &str) -> Result<(), LifxError> {
+            let label = label_cstring(label)?;
+            let payload: Message = Message::SetLabel {
+                label: LifxString::new(&label),
+            };
+            self.send(sock, payload)?;
+            self.set_name(label, LabelSource::SetLabel);
+            Ok(())
+        }
+
+        /// Moves the bulb to the location with ID `location_id` and label `label`, and updates
+        /// the cached location to match straight away.
+        ///
+        /// `updated_at` is when the location was last changed, in nanoseconds since the epoch.
+        /// To rename a location, give every bulb in it the new label with a later `updated_at`;
+        /// the LIFX app shows the label with the latest one.  Fails like [`BulbInfo::set_label`]
+        /// if the label is invalid.
+        pub fn set_location(
+            &mut self,
+            sock: &dyn Transport,
+            location_id: [u8; 16],
+            label: &str,
+            updated_at: u64,
+        ) -> Result<(), LifxError> {
+            let label = label_cstring(label)?;
+            let payload: Message = Message::SetLocation {
+                location: LifxIdent(location_id),
+                label: LifxString::new(&label),
+                updated_at,
+            };
+            self.send(sock, payload)?;
+            self.location.update((label, updated_at));
+            self.location_id = Some(location_id);
+            Ok(())
+        }
+
+        /// Where the cached [`BulbInfo::name`] came from, or `None` if it hasn't been set yet.
+        pub fn name_source(&self) -> Option<LabelSource> {
+            self.name_source
+        }
+
+        fn set_name(&mut self, label: CString, source: LabelSource) {
+            self.name.update(label);
+            self.name_source = Some(source);
+        }
+
+        /// The maximum brightness of the infrared channel, if the bulb has reported it.
+        pub fn infrared_brightness(&self) -> Option<u16> {
+            self.infrared.as_ref().copied()
+        }
+
+        /// Sets the maximum brightness of the infrared channel.
+        ///
+        /// Only bulbs whose product info reports infrared support will honor this.
+        pub fn set_infrared(&self, sock: &dyn Transport, brightness: u16) -> Result<(), LifxError> {
+            let payload: Message = Message::LightSetInfrared { brightness };
+            self.send(sock, payload)
+        }
+
+        /// Starts (or with `enable` false, stops) an HEV clean cycle lasting `duration_s` seconds.
+        ///
+        /// A `duration_s` of 0 uses the bulb's default cycle length.  Fails with
+        /// [`LifxError::Unsupported`] unless the bulb's product info reports HEV support.
+        pub fn set_hev_cycle(
+            &self,
+            sock: &dyn Transport,
+            enable: bool,
+            duration_s: u32,
+        ) -> Result<(), LifxError> {
+            if !self.capabilities().is_some_and(|info| info.hev) {
+                return Err(LifxError::Unsupported);
+            }
+            let payload: Message = Message::LightSetHevCycle {
+                enable,
+                duration: duration_s,
+            };
+            self.send(sock, payload)
+        }
+
+        /// How much of the current HEV clean cycle is left, if the bulb has reported one.
+        ///
+        /// This counts down from the last report, so it stays useful between refreshes.
+        pub fn hev_remaining(&self) -> Option<Duration> {
+            let remaining = Duration::from_secs((*self.hev_cycle.as_ref()?).into());
+            Some(remaining.saturating_sub(self.hev_cycle.last_updated.elapsed()))
+        }
+
+        pub fn set_bulb_color(
+            &self,
+            sock: &dyn Transport,
+            color: HSBK,
+            duration: u32,
+        ) -> Result<(), LifxError> {
+            let payload: Message = Message::LightSetColor {
+                reserved: 0,
+                color,
+                duration,
+            };
+            self.send(sock, payload)
+        }
+
+        /// Like [`BulbInfo::set_bulb_color`], but without asking for an acknowledgement, so the
+        /// message is never retransmitted.  See [`AckMode`] for when that's worthwhile.
+        pub fn set_bulb_color_unacked(
+            &self,
+            sock: &dyn Transport,
+            color: HSBK,
+            duration: u32,
+        ) -> Result<(), LifxError> {
+            let payload: Message = Message::LightSetColor {
+                reserved: 0,
+                color,
+                duration,
+            };
+            self.send_with_ack(sock, payload, AckMode::Unacked)
+        }
+
+        /// The cached color of a single-zone bulb, or a neutral white if it isn't known yet.
+        fn base_color(&self) -> HSBK {
+            self.current_color()
+                .unwrap_or_else(|| HsbkBuilder::new().build())
+        }
+
+        /// Changes only the brightness, keeping the bulb's current hue, saturation and kelvin.
+        ///
+        /// If the bulb's color hasn't been reported yet, a neutral white is used instead.
+        pub fn set_brightness(
+            &self,
+            sock: &dyn Transport,
+            brightness: u16,
+            duration: u32,
+        ) -> Result<(), LifxError> {
+            let color = HSBK {
+                brightness,
+                ..self.base_color()
+            };
+            self.set_bulb_color(sock, color, duration)
+        }
+
+        /// Changes only the color temperature.  See [`BulbInfo::set_brightness`].
+        pub fn set_kelvin(
+            &self,
+            sock: &dyn Transport,
+            kelvin: u16,
+            duration: u32,
+        ) -> Result<(), LifxError> {
+            let color = HSBK {
+                kelvin,
+                ..self.base_color()
+            };
+            self.set_bulb_color(sock, color, duration)
+        }
+
+        /// Changes only the hue.  See [`BulbInfo::set_brightness`].
+        pub fn set_hue(
+            &self,
+            sock: &dyn Transport,
+            hue: u16,
+            duration: u32,
+        ) -> Result<(), LifxError> {
+            let color = HSBK {
+                hue,
+                ..self.base_color()
+            };
+            self.set_bulb_color(sock, color, duration)
+        }
+
+        /// Like [`BulbInfo::set_bulb_color`], but first clamps `color.kelvin` to
+        /// [`BulbInfo::kelvin_range`].  The color is sent unchanged if the range isn't known.
+        pub fn set_bulb_color_clamped(
+            &self,
+            sock: &dyn Transport,
+            mut color: HSBK,
+            duration: u32,
+        ) -> Result<(), LifxError> {
+            if let Some((min, max)) = self.kelvin_range() {
+                color.kelvin = color.kelvin.clamp(min, max);
+            }
+            self.set_bulb_color(sock, color, duration)
+        }
+        /// Runs one of the bulb's built-in waveform effects.
+        ///
+        /// `period_ms` is the length of one cycle and `cycles` the number of cycles to run.
+        /// `skew_ratio` maps [-32768, 32767] onto [0, 1] and only affects [`Waveform::Pulse`].
+        /// If `transient` is true, the bulb returns to its original color once the effect ends.
+        #[allow(clippy::too_many_arguments)]
+        pub fn set_waveform(
+            &self,
+            sock: &dyn Transport,
+            transient: bool,
+            color: HSBK,
+            period_ms: u32,
+            cycles: f32,
+            skew_ratio: i16,
+            waveform: Waveform,
+        ) -> Result<(), LifxError> {
+            let payload: Message = Message::SetWaveform {
+                reserved: 0,
+                transient,
+                color,
+                period: period_ms,
+                cycles,
+                skew_ratio,
+                waveform,
+            };
+            self.send(sock, payload)
+        }
+
+        /// Like [`BulbInfo::set_waveform`], but only the channels whose `set_*` flag is true are
+        /// animated; the rest keep their current values.  For example, setting only
+        /// `set_brightness` pulses the brightness without changing the bulb's color.
+        ///
+        /// Fails with [`LifxError::NoChannels`] if every flag is false.
+        #[allow(clippy::too_many_arguments)]
+        pub fn set_waveform_optional(
+            &self,
+            sock: &dyn Transport,
+            transient: bool,
+            color: HSBK,
+            period_ms: u32,
+            cycles: f32,
+            skew_ratio: i16,
+            waveform: Waveform,
+            set_hue: bool,
+            set_saturation: bool,
+            set_brightness: bool,
+            set_kelvin: bool,
+        ) -> Result<(), LifxError> {
+            if !(set_hue || set_saturation || set_brightness || set_kelvin) {
+                return Err(LifxError::NoChannels);
+            }
+            let payload: Message = Message::SetWaveformOptional {
+                reserved: 0,
+                transient,
+                color,
+                period: period_ms,
+                cycles,
+                skew_ratio,
+                waveform,
+                set_hue,
+                set_saturation,
+                set_brightness,
+                set_kelvin,
+            };
+            self.send(sock, payload)
+        }
+
+        /// Sets zones `start_index..=end_index` to a single color, using the legacy multizone
+        /// message understood by strips that don't support extended multizone.
+        ///
+        /// Changes are buffered by the strip until a message with [`ApplicationRequest::Apply`] or
+        /// [`ApplicationRequest::ApplyOnly`] arrives.
+        pub fn set_color_zones(
+            &self,
+            sock: &dyn Transport,
+            start_index: u8,
+            end_index: u8,
+            color: HSBK,
+            duration: u32,
+            apply: ApplicationRequest,
+        ) -> Result<(), LifxError> {
+            let payload: Message = Message::SetColorZones {
+                start_index,
+                end_index,
+                color,
+                duration,
+                apply,
+            };
+            self.send(sock, payload)
+        }
+
+        /// Whether this device is a switch with relays, rather than a light.
+        pub fn is_switch(&self) -> bool {
+            self.capabilities().is_some_and(|info| info.relays)
+        }
+
+        /// The power level of one of a switch's relays, if the switch has reported it.
+        ///
+        /// Current switches can't dim, so this is either 0 for off or 65535 for on.
+        pub fn relay_power(&self, relay_index: u8) -> Option<u16> {
+            self.relays.get(relay_index as usize)?.as_ref().copied()
+        }
+
+        /// Turns one of a switch's relays on (65535) or off (0).
+        ///
+        /// Fails with [`LifxError::Unsupported`] unless the device is a switch, or
+        /// [`LifxError::NoSuchRelay`] if `relay_index` is out of range.
+        pub fn set_relay_power(
+            &self,
+            sock: &dyn Transport,
+            relay_index: u8,
+            level: u16,
+        ) -> Result<(), LifxError> {
+            if !self.is_switch() {
+                return Err(LifxError::Unsupported);
+            }
+            if relay_index as usize >= self.relays.len() {
+                return Err(LifxError::NoSuchRelay(relay_index));
+            }
+            let payload: Message = Message::RelaySetPower { relay_index, level };
+            self.send(sock, payload)
+        }
+
+        /// Starts one of a strip's built-in effects, which the strip animates by itself.
+        ///
+        /// `speed` is how long one cycle of the effect takes, and `direction` only affects
+        /// [`MultiZoneEffectType::Move`].  The effect runs until it's stopped with
+        /// [`BulbInfo::stop_multizone_effect`] or the strip's colors are changed.  Fails with
+        /// [`LifxError::Unsupported`] unless the bulb is a strip.
+        pub fn set_multizone_effect(
+            &self,
+            sock: &dyn Transport,
+            effect: MultiZoneEffectType,
+            speed: Duration,
+            direction: Direction,
+        ) -> Result<(), LifxError> {
+            if !self.supports_multizone() {
+                return Err(LifxError::Unsupported);
+            }
+            let mut parameters = [0; 8];
+            // the second parameter of a move effect is its direction
+            parameters[1] = match direction {
+                Direction::Right => 0,
+                Direction::Left => 1,
+            };
+            // strips only use this to tell effects apart, so it just needs to change each time
+            let instance_id = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.subsec_nanos());
+            let payload: Message = Message::SetMultiZoneEffect {
+                instance_id,
+                typ: effect,
+                reserved: 0,
+                speed: speed.as_millis().try_into().unwrap_or(u32::MAX),
+                // zero runs the effect until it's replaced
+                duration: 0,
+                reserved7: 0,
+                reserved8: 0,
+                parameters,
+            };
+            self.send(sock, payload)
+        }
+
+        /// Stops whatever effect was started with [`BulbInfo::set_multizone_effect`], leaving the
+        /// zones as they are at that moment.
+        pub fn stop_multizone_effect(&self, sock: &dyn Transport) -> Result<(), LifxError> {
+            self.set_multizone_effect(
+                sock,
+                MultiZoneEffectType::Off,
+                Duration::ZERO,
+                Direction::default(),
+            )
+        }
+
+        /// What this bulb's product is capable of.
+        ///
+        /// This is `None` until the bulb has reported its model, or if the model isn't one we
+        /// know about.
+        pub fn capabilities(&self) -> Option<ProductInfo> {
+            self.model
+                .as_ref()
+                .and_then(|(vendor, product)| get_product_info(*vendor, *product))
+                .copied()
+        }
+
+        /// Everything this bulb's product is capable of.
+        ///
+        /// Like [`BulbInfo::capabilities`], this is `None` until the bulb has reported its model,
+        /// or if the model isn't one we know about.
+        pub fn features(&self) -> Option<Features> {
+            self.capabilities().as_ref().map(Features::from)
+        }
+
+        /// Whether this bulb can display colors, rather than just shades of white.
+        pub fn supports_color(&self) -> bool {
+            self.capabilities().is_some_and(|info| info.color)
+        }
+
+        /// Whether this bulb is a strip with individually addressable zones.
+        pub fn supports_multizone(&self) -> bool {
+            self.capabilities().is_some_and(|info| info.multizone)
+        }
+
+        /// Whether this bulb can emit infrared light.
+        pub fn supports_infrared(&self) -> bool {
+            self.capabilities().is_some_and(|info| info.infrared)
+        }
+
+        /// The lowest and highest color temperature this bulb supports, in kelvin.
+        ///
+        /// Bulbs with a fixed temperature report the same value for both.  This is `None` until
+        /// the model is known, and for devices that aren't lights.
+        pub fn kelvin_range(&self) -> Option<(u16, u16)> {
+            match self.capabilities()?.temperature_range {
+                TemperatureRange::Variable { min, max } => Some((min, max)),
+                TemperatureRange::Fixed(kelvin) => Some((kelvin, kelvin)),
+                TemperatureRange::None => None,
+            }
+        }
+
+        /// Whether this bulb understands the extended multizone messages.
+        fn supports_extended_zones(&self) -> bool {
+            self.capabilities().is_some_and(|info| info.extended)
+        }
+
+        /// Sets one zone per entry in `colors`, starting at `zone_index`.
+        ///
+        /// Fails with [`LifxError::InvalidZoneCount`] if the range runs past the end of the
+        /// strip, or [`LifxError::NoZones`] if the strip hasn't reported its zones yet.
+        ///
+        /// Strips that support extended multizone are updated with one message per 82 zones.
+        /// Older strips fall back to one [`Message::SetColorZones`] per run of same-colored zones,
+        /// which may trip the rate limit for long, varied strips.
+        ///
+        /// `apply` controls when the strip shows the new colors:
+        ///
+        /// * [`ApplicationRequest::Apply`] shows them as soon as they've all been sent, along with
+        ///   any changes staged earlier.
+        /// * [`ApplicationRequest::NoApply`] only stages them, so several calls can be shown at
+        ///   once with a later `Apply`, or with [`BulbInfo::apply_zones`].
+        /// * [`ApplicationRequest::ApplyOnly`] stages them and then sends a separate message that
+        ///   shows everything staged so far.
+        pub fn set_strip_array(
+            &self,
+            sock: &dyn Transport,
+            colors: &[HSBK],
+            zone_index: u16,
+            duration: u32,
+            apply: ApplicationRequest,
+        ) -> Result<(), LifxError> {
+            let extended = self.supports_extended_zones();
+            let available = self.zone_count()?;
+            let requested = zone_index as usize + colors.len();
+            if requested > available as usize {
+                return Err(LifxError::InvalidZoneCount {
+                    requested,
+                    available,
+                });
+            }
+            // only the last message carries `apply`, so nothing is shown half-written
+            let final_apply = match apply {
+                ApplicationRequest::Apply => ApplicationRequest::Apply,
+                // an ApplyOnly message would discard its own colors, so commit separately
+                ApplicationRequest::NoApply | ApplicationRequest::ApplyOnly => {
+                    ApplicationRequest::NoApply
+                }
+            };
+            if extended {
+                let chunks = colors.chunks(EXTENDED_ZONES_PER_MESSAGE);
+                let last = chunks.len().saturating_sub(1);
+                for (i, chunk) in chunks.enumerate() {
+                    // the message always carries 82 colors; only the first colors_count are used
+                    let mut padded = Box::new([chunk[0]; EXTENDED_ZONES_PER_MESSAGE]);
+                    padded[..chunk.len()].copy_from_slice(chunk);
+                    let apply = if i == last {
+                        final_apply
+                    } else {
+                        ApplicationRequest::NoApply
+                    };
+                    let payload: Message = Message::SetExtendedColorZones {
+                        duration,
+                        apply,
+                        zone_index: zone_index + (i * EXTENDED_ZONES_PER_MESSAGE) as u16,
+                        colors_count: chunk.len() as u8,
+                        colors: padded,
+                    };
+                    self.send(sock, payload)?;
+                }
+            } else {
+                self.set_legacy_zones(sock, colors, zone_index, duration, final_apply)?;
+            }
+            if apply == ApplicationRequest::ApplyOnly {
+                self.apply_zones(sock, duration)?;
+            }
+            Ok(())
+        }
+
+        /// The number of zones on this strip, from whichever message type it supports.
+        fn zone_count(&self) -> Result<u16, LifxError> {
+            if self.supports_extended_zones() {
+                Ok(self.zones.as_ref().ok_or(LifxError::NoZones)?.zones_count)
+            } else {
+                match &self.color {
+                    Color::Multi(d) => Ok(d.as_ref().ok_or(LifxError::NoZones)?.len() as u16),
+                    _ => Err(LifxError::NoZones),
+                }
+            }
+        }
+
+        /// When each of the most recent messages was sent to this bulb, and its type, oldest
+        /// first.
+        ///
+        /// This is empty unless the log was turned on with [`Manager::set_command_log_len`].
+        /// Retransmits aren't logged again.
+        pub fn command_history(&self) -> Vec<(Instant, &'static str)> {
+            let log = self
+                .command_log
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            log.iter().copied().collect()
+        }
+
+        fn log_command(&self, name: &'static str) {
+            let len = self.command_log_len.load(Ordering::Relaxed);
+            let mut log = self
+                .command_log
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if len > 0 {
+                log.push_back((Instant::now(), name));
+            }
+            // also trims the log if it was made shorter
+            while log.len() > len {
+                log.pop_front();
+            }
+        }
+
+        /// When the strip last acknowledged a message setting its zones, or `None` if it never
+        /// has.
+        pub fn last_zone_ack(&self) -> Option<Instant> {
+            self.last_zone_ack
+        }
+
+        /// Whether the strip is ready for the next frame of an animation: every zone change
+        /// sent so far has been acknowledged, or was sent more than `timeout` ago.
+        ///
+        /// Only changes sent with [`AckMode::Acked`] are waited for.
+        pub fn zone_frame_ready(&self, timeout: Duration) -> bool {
+            let outstanding = self
+                .outstanding
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            !outstanding
+                .values()
+                .any(|pending| pending.zones && pending.sent_at.elapsed() < timeout)
+        }
+
+        /// Whether every zone of this strip has reported its color since we started tracking it.
+        ///
+        /// This is always `false` for bulbs that aren't strips.
+        pub fn zones_complete(&self) -> bool {
+            match &self.color {
+                Color::Multi(d) => d
+                    .as_ref()
+                    .is_some_and(|zones| !zones.is_empty() && zones.iter().all(Option::is_some)),
+                _ => false,
+            }
+        }
+
+        /// Asks the strip for the colors of zones `start..=end` only.
+        ///
+        /// The replies are merged into [`BulbInfo::color`] as they arrive, leaving every other
+        /// zone as it was, so this is cheaper than a full refresh when only part of a long strip
+        /// is changing.  Fails with [`LifxError::Unsupported`] unless the bulb is a strip.
+        pub fn refresh_zone_range(
+            &self,
+            sock: &dyn Transport,
+            start: u8,
+            end: u8,
+        ) -> Result<(), LifxError> {
+            if !self.supports_multizone() {
+                return Err(LifxError::Unsupported);
+            }
+            let payload: Message = Message::GetColorZones {
+                start_index: start,
+                end_index: end,
+            };
+            self.send(sock, payload)
+        }
+
+        /// Sets the whole strip from `colors`, one per zone starting at the first.
+        ///
+        /// Extra colors are ignored, and if there are fewer colors than zones the last color is
+        /// repeated to fill the rest of the strip.  Fails with [`LifxError::NoColors`] if
+        /// `colors` is empty, or [`LifxError::NoZones`] if the strip hasn't reported its zones
+        /// yet.
+        ///
+        /// Like [`BulbInfo::set_strip_array`], strips longer than 82 zones get several messages,
+        /// and only the last one applies them, so the whole strip changes at once.
+        pub fn set_zones_slice(
+            &self,
+            sock: &dyn Transport,
+            colors: &[HSBK],
+            duration: u32,
+        ) -> Result<(), LifxError> {
+            let last = *colors.last().ok_or(LifxError::NoColors)?;
+            let zone_count = self.zone_count()? as usize;
+            let mut colors = colors[..colors.len().min(zone_count)].to_vec();
+            colors.resize(zone_count, last);
+            self.set_strip_array(sock, &colors, 0, duration, ApplicationRequest::Apply)
+        }
+
+        /// Fades the whole strip to a gradient running from `start` at the first zone to `end`
+        /// at the last.  See [`color::gradient`](crate::color::gradient) for `shortest_hue`.
+        ///
+        /// Fails with [`LifxError::NoZones`] if the strip hasn't reported its zones yet.
+        pub fn set_gradient(
+            &self,
+            sock: &dyn Transport,
+            start: HSBK,
+            end: HSBK,
+            duration: u32,
+            shortest_hue: bool,
+        ) -> Result<(), LifxError> {
+            let colors = gradient(start, end, self.zone_count()? as usize, shortest_hue);
+            self.set_strip_array(sock, &colors, 0, duration, ApplicationRequest::Apply)
+        }
+
+        /// Shows every zone change staged with [`ApplicationRequest::NoApply`].
+        pub fn apply_zones(&self, sock: &dyn Transport, duration: u32) -> Result<(), LifxError> {
+            // with ApplyOnly, the colors in the message itself are ignored
+            let payload: Message = if self.supports_extended_zones() {
+                Message::SetExtendedColorZones {
+                    duration,
+                    apply: ApplicationRequest::ApplyOnly,
+                    zone_index: 0,
+                    colors_count: 0,
+                    colors: Box::new([BLACK; EXTENDED_ZONES_PER_MESSAGE]),
+                }
+            } else {
+                Message::SetColorZones {
+                    start_index: 0,
+                    end_index: 0,
+                    color: BLACK,
+                    duration,
+                    apply: ApplicationRequest::ApplyOnly,
+                }
+            };
+            self.send(sock, payload)
+        }
+
+        /// The legacy half of [`BulbInfo::set_strip_array`], after the bounds have been checked.
+        fn set_legacy_zones(
+            &self,
+            sock: &dyn Transport,
+            colors: &[HSBK],
+            zone_index: u16,
+            duration: u32,
+            final_apply: ApplicationRequest,
+        ) -> Result<(), LifxError> {
+            // legacy strips have at most 255 zones, which the bounds check above guarantees
+            let mut start = 0;
+            while start < colors.len() {
+                let mut end = start;
+                while end + 1 < colors.len() && colors[end + 1] == colors[start] {
+                    end += 1;
+                }
+                let apply = if end + 1 == colors.len() {
+                    final_apply
+                } else {
+                    ApplicationRequest::NoApply
+                };
+                self.set_color_zones(
+                    sock,
+                    (zone_index as usize + start) as u8,
+                    (zone_index as usize + end) as u8,
+                    colors[start],
+                    duration,
+                    apply,
+                )?;
+                start = end + 1;
+            }
+            Ok(())
+        }
+
+        /// Sets the pixels of `length` tiles, starting at `tile_index`, to `colors`.
+        ///
+        /// `colors` holds up to 64 pixels, row by row; any pixels past the end of it are set to
+        /// black.  Fails with [`LifxError::Unsupported`] unless the bulb's product info reports
+        /// chain or matrix support.
+        pub fn set_tile_64(
+            &self,
+            sock: &dyn Transport,
+            tile_index: u8,
+            length: u8,
+            colors: &[HSBK],
+            duration: u32,
+        ) -> Result<(), LifxError> {
+            if !self
+                .capabilities()
+                .is_some_and(|info| info.chain || info.matrix)
+            {
+                return Err(LifxError::Unsupported);
+            }
+            if colors.len() > TILE_PIXELS_PER_MESSAGE {
+                return Err(LifxError::InvalidZoneCount {
+                    requested: colors.len(),
+                    available: TILE_PIXELS_PER_MESSAGE as u16,
+                });
+            }
+            let width = match &self.color {
+                Color::Tiles(d) => d
+                    .as_ref()
+                    .and_then(|tiles| tiles.get(tile_index as usize))
+                    .map(|tile| tile.width),
+                _ => None,
+            };
+            let mut padded = Box::new([BLACK; TILE_PIXELS_PER_MESSAGE]);
+            padded[..colors.len()].copy_from_slice(colors);
+            let payload: Message = Message::SetTileState64 {
+                tile_index,
+                length,
+                reserved: 0,
+                x: 0,
+                y: 0,
+                width: width.unwrap_or(8),
+                duration,
+                colors: padded,
+            };
+            self.send(sock, payload)
+        }
+
+        /// A copy of every color we know about for this bulb, used to detect changes.
+        fn color_snapshot(&self) -> ColorSnapshot {
+            ColorSnapshot {
+                single: match &self.color {
+                    Color::Single(d) => d.data,
+                    _ => None,
+                },
+                multi: match &self.color {
+                    Color::Multi(d) => d.data.clone(),
+                    _ => None,
+                },
+                extended: self.zones.as_ref().map(|zones| zones.colors.clone()),
+                tiles: match &self.color {
+                    Color::Tiles(d) => d
+                        .as_ref()
+                        .map(|tiles| tiles.iter().map(|tile| tile.colors.clone()).collect()),
+                    _ => None,
+                },
+            }
+        }
+
+        /// The messages needed to fetch every piece of info that is missing or out of date.
+        pub(crate) fn missing_info_messages(&self) -> Vec<Message> {
+            let mut pending: Vec<Message> = Vec::new();
+            Self::push_if_needed(&mut pending, &self.name);
+            Self::push_if_needed(&mut pending, &self.model);
+            Self::push_if_needed(&mut pending, &self.location);
+            Self::push_if_needed(&mut pending, &self.group);
+            Self::push_if_needed(&mut pending, &self.host_firmware);
+            Self::push_if_needed(&mut pending, &self.wifi_firmware);
+            Self::push_if_needed(&mut pending, &self.wifi_signal);
+            Self::push_if_needed(&mut pending, &self.power_level);
+            self.push_color_if_needed(&mut pending);
+            if let Some(info) = self.capabilities() {
+                if info.extended {
+                    Self::push_if_needed(&mut pending, &self.zones);
+                }
+                if info.infrared {
+                    Self::push_if_needed(&mut pending, &self.infrared);
+                }
+                if info.hev {
+                    Self::push_if_needed(&mut pending, &self.hev_cycle);
+                }
+                if info.relays {
+                    for relay in &self.relays {
+                        Self::push_if_needed(&mut pending, relay);
+                    }
+                }
+            }
+            pending
+        }
+
+        fn query_for_missing_info(&self, sock: &dyn Transport) -> Result<(), LifxError> {
+            self.refresh_if_needed(sock, self.missing_info_messages())
+        }
+
+        /// Sends each of `pending`, the refresh messages for some stale part of the cache.
+        fn refresh_if_needed(
+            &self,
+            sock: &dyn Transport,
+            pending: Vec<Message>,
+        ) -> Result<(), LifxError> {
+            for payload in pending {
+                self.send(sock, payload)?;
+            }
+            Ok(())
+        }
+
+        /// Asks the bulb for its power level, if the cached one is missing or out of date.
+        ///
+        /// Like [`Manager::refresh`], but for just one part of the cache, so callers can poll the
+        /// things that change often without re-reading ones that rarely do, such as firmware.
+        pub fn refresh_power(&self, sock: &dyn Transport) -> Result<(), LifxError> {
+            let mut pending = Vec::new();
+            Self::push_if_needed(&mut pending, &self.power_level);
+            self.refresh_if_needed(sock, pending)
+        }
+
+        /// Asks the bulb for its color, if the cached one is missing or out of date.
+        ///
+        /// For strips this is the color of every zone, and for chains it's every tile's pixels.
+        /// Nothing is sent until the bulb has reported its model.
+        pub fn refresh_color(&self, sock: &dyn Transport) -> Result<(), LifxError> {
+            let mut pending = Vec::new();
+            self.push_color_if_needed(&mut pending);
+            self.refresh_if_needed(sock, pending)
+        }
+
+        /// Asks a strip for its zones, if the cached ones are missing or out of date.
+        ///
+        /// Strips that support extended multizone refresh [`BulbInfo::zones`], and older ones
+        /// refresh [`BulbInfo::color`].  Fails with [`LifxError::Unsupported`] unless the bulb
+        /// is a strip.
+        pub fn refresh_zones(&self, sock: &dyn Transport) -> Result<(), LifxError> {
+            if !self.supports_multizone() {
+                return Err(LifxError::Unsupported);
+            }
+            let mut pending = Vec::new();
+            if self.supports_extended_zones() {
+                Self::push_if_needed(&mut pending, &self.zones);
+            } else if let Color::Multi(d) = &self.color {
+                Self::push_if_needed(&mut pending, d);
+            }
+            self.refresh_if_needed(sock, pending)
+        }
+
+        fn push_color_if_needed(&self, pending: &mut Vec<Message>) {
+            match &self.color {
+                Color::Unknown => (), // we'll need to wait to get info about this bulb's model, so we'll know if it's multizone or not
+                Color::Single(d) => Self::push_if_needed(pending, d),
+                Color::Multi(d) => Self::push_if_needed(pending, d),
+                Color::Tiles(d) => {
+                    // pixels are re-read whenever the chain itself is
+                    let stale = d.is_stale();
+                    Self::push_if_needed(pending, d);
+                    for (tile_index, tile) in d.as_ref().into_iter().flatten().enumerate() {
+                        if stale || tile.colors.is_none() {
+                            pending.push(Message::GetTileState64 {
+                                tile_index: tile_index as u8,
+                                length: 1,
+                                reserved: 0,
+                                x: 0,
+                                y: 0,
+                                width: tile.width,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// How long ago each piece of a bulb's cached state was fetched; see
+    /// [`BulbInfo::field_ages`].
+    ///
+    /// Anything that has never been fetched is `None`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct BulbFieldAges {
+        pub name: Option<Duration>,
+        pub model: Option<Duration>,
+        pub location: Option<Duration>,
+        pub group: Option<Duration>,
+        pub host_firmware: Option<Duration>,
+        pub wifi_firmware: Option<Duration>,
+        pub wifi_signal: Option<Duration>,
+        pub power: Option<Duration>,
+        pub infrared: Option<Duration>,
+        pub hev_cycle: Option<Duration>,
+        pub color: Option<Duration>,
+        pub zones: Option<Duration>,
+    }
+
+    /// What a device can do, as reported by [`BulbInfo::features`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+    pub struct Features {
+        /// The light can show colors, rather than just shades of white.
+        pub color: bool,
+        /// The light can emit infrared.
+        pub infrared: bool,
+        /// The light is a strip with individually addressable zones.
+        pub multizone: bool,
+        /// The strip understands the extended multizone messages.
+        pub extended_multizone: bool,
+        /// The device is made of a chain of separate pieces, like the LIFX Tile.
+        pub chain: bool,
+        /// The light can run HEV cleaning cycles.
+        pub hev: bool,
+        /// The light has a 2D grid of pixels, like the LIFX Tile and Candle.
+        pub matrix: bool,
+        /// The device has relays, like the LIFX Switch.
+        pub relays: bool,
+        /// The device has physical buttons.
+        pub buttons: bool,
+        /// The light's color temperature can be changed.
+        pub variable_temperature: bool,
+    }
+
+    impl From<&ProductInfo> for Features {
+        fn from(info: &ProductInfo) -> Features {
+            Features {
+                color: info.color,
+                infrared: info.infrared,
+                multizone: info.multizone,
+                extended_multizone: info.extended,
+                chain: info.chain,
+                hev: info.hev,
+                matrix: info.matrix,
+                relays: info.relays,
+                buttons: info.buttons,
+                variable_temperature: matches!(
+                    info.temperature_range,
+                    TemperatureRange::Variable { .. }
+                ),
+            }
+        }
+    }
+
+    /// A plain copy of a bulb's cached state, suitable for serializing.
+    ///
+    /// Anything the bulb hasn't reported yet is `None`.
+    #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+    pub struct BulbSnapshot {
+        /// The target id, as 16 hex digits.
+        pub target: String,
+        pub ip: IpAddr,
+        pub label: Option<String>,
+        pub location: Option<String>,
+        pub group: Option<String>,
+        pub model: Option<String>,
+        pub power: Option<bool>,
+        pub color: Option<SnapshotColor>,
+        /// Host firmware version, as `major.minor`.
+        pub host_firmware: Option<String>,
+        /// Wifi firmware version, as `major.minor`.
+        pub wifi_firmware: Option<String>,
+    }
+
+    /// Everything a bulb has reported, copied together by [`BulbInfo::state`].
+    ///
+    /// Unlike [`BulbSnapshot`], the fields keep the types they're cached with, so two states can
+    /// be compared to see exactly what changed between polls.  Anything the bulb hasn't reported
+    /// yet is `None`.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct BulbState {
+        pub target: u64,
+        /// The address messages are sent to.
+        pub addr: SocketAddr,
+        /// See [`BulbInfo::online`].
+        pub online: bool,
+        pub label: Option<CString>,
+        pub location: Option<CString>,
+        pub group: Option<CString>,
+        /// The vendor and product ids.
+        pub model: Option<(u32, u32)>,
+        /// 0 when off, 65535 when on.
+        pub power_level: Option<u16>,
+        pub color: Option<SnapshotColor>,
+        pub infrared: Option<u16>,
+        /// Seconds left in the HEV clean cycle, as last reported.
+        pub hev_cycle: Option<u32>,
+        pub wifi_signal: Option<f32>,
+    }
+
+    /// A bulb saved by [`Manager::save_known_bulbs`].
+    #[cfg(feature = "serde")]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct KnownBulb {
+        target: u64,
+        addr: SocketAddr,
+        label: Option<String>,
+    }
+
+    /// The color part of a [`BulbSnapshot`].
+    #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize))]
+    #[cfg_attr(feature = "serde", serde(untagged))]
+    pub enum SnapshotColor {
+        Single(HSBK),
+        /// One entry per zone; zones we haven't heard about yet are `None`.
+        Zones(Vec<Option<HSBK>>),
+        /// One entry per tile, holding its pixels row by row; tiles we haven't heard about yet
+        /// are `None`.
+        Tiles(Vec<Option<Vec<HSBK>>>),
+    }
+
+    impl BulbInfo {
+        /// The bulb's cached color, in whichever shape suits the kind of device it is.
+        fn snapshot_color(&self) -> Option<SnapshotColor> {
+            match &self.color {
+                Color::Unknown => None,
+                Color::Single(d) => d.as_ref().copied().map(SnapshotColor::Single),
+                Color::Multi(d) => d.as_ref().cloned().map(SnapshotColor::Zones),
+                Color::Tiles(d) => d.as_ref().map(|tiles| {
+                    SnapshotColor::Tiles(
+                        tiles
+                            .iter()
+                            .map(|tile| tile.colors.as_ref().map(|colors| colors.to_vec()))
+                            .collect(),
+                    )
+                }),
+            }
+        }
+
+        /// How old each piece of cached state is, for showing which data may be out of date.
+        pub fn field_ages(&self) -> BulbFieldAges {
+            BulbFieldAges {
+                name: self.name.age(),
+                model: self.model.age(),
+                location: self.location.age(),
+                group: self.group.age(),
+                host_firmware: self.host_firmware.age(),
+                wifi_firmware: self.wifi_firmware.age(),
+                wifi_signal: self.wifi_signal.age(),
+                power: self.power_level.age(),
+                infrared: self.infrared.age(),
+                hev_cycle: self.hev_cycle.age(),
+                color: match &self.color {
+                    Color::Unknown => None,
+                    Color::Single(d) => d.age(),
+                    Color::Multi(d) => d.age(),
+                    Color::Tiles(d) => d.age(),
+                },
+                zones: self.zones.age(),
+            }
+        }
+
+        /// Copies everything the bulb has reported into a [`BulbState`].
+        ///
+        /// The worker thread only updates a bulb while it holds the bulb table lock, and a
+        /// `&BulbInfo` can only be had while holding the same lock (see [`Manager::with_bulb`]),
+        /// so the copy never mixes fields from before and after a message is handled.
+        pub fn state(&self) -> BulbState {
+            BulbState {
+                target: self.options.target.unwrap_or_default(),
+                addr: self.addr,
+                online: self.online,
+                label: self.name.as_ref().cloned(),
+                location: self.location_label().map(CStr::to_owned),
+                group: self.group_label().map(CStr::to_owned),
+                model: self.model.as_ref().copied(),
+                power_level: self.power_level.as_ref().copied(),
+                color: self.snapshot_color(),
+                infrared: self.infrared.as_ref().copied(),
+                hev_cycle: self.hev_cycle.as_ref().copied(),
+                wifi_signal: self.wifi_signal.as_ref().copied(),
+            }
+        }
+
+        /// Copies the current cached state into a [`BulbSnapshot`].
+        pub fn snapshot(&self) -> BulbSnapshot {
+            BulbSnapshot {
+                target: format!("{:0>16X}", self.options.target.unwrap_or_default()),
+                ip: self.addr.ip(),
+                label: self.name.as_ref().map(|s| s.to_string_lossy().into_owned()),
+                location: self
+                    .location_label()
+                    .map(|s| s.to_string_lossy().into_owned()),
+                group: self.group_label().map(|s| s.to_string_lossy().into_owned()),
+                model: self.model.as_ref().map(|(vendor, product)| {
+                    match get_product_info(*vendor, *product) {
+                        Some(info) => info.name.to_owned(),
+                        None => format!("Unknown model (vendor={}, product={})", vendor, product),
+                    }
+                }),
+                power: self.is_on(),
+                color: self.snapshot_color(),
+                host_firmware: self
+                    .host_firmware
+                    .as_ref()
+                    .map(|(major, minor, _)| format!("{}.{}", major, minor)),
+                wifi_firmware: self
+                    .wifi_firmware
+                    .as_ref()
+                    .map(|(major, minor, _)| format!("{}.{}", major, minor)),
+            }
+        }
+    }
+
+    /// Writes a firmware build timestamp (nanoseconds since the epoch) as seconds since the epoch.
+    fn write_build_time(f: &mut std::fmt::Formatter<'_>, build: u64) -> std::fmt::Result {
+        if build > 0 {
+            write!(f, " (built {})", build / 1_000_000_000)?;
+        }
+        Ok(())
+    }
+
+    /// A short summary for showing to users, like `Kitchen: On, 80% White (Warm)`.
+    impl std::fmt::Display for BulbInfo {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self.name.as_ref() {
+                Some(name) => write!(f, "{}", name.to_string_lossy())?,
+                None => write!(
+                    f,
+                    "{}",
+                    target_to_mac(self.options.target.unwrap_or_default())
+                )?,
+            }
+            match self.power_level.as_ref() {
+                Some(0) => write!(f, ": Off")?,
+                Some(_) => write!(f, ": On")?,
+                None => write!(f, ": ??")?,
+            }
+            match &self.color {
+                Color::Unknown => {}
+                Color::Single(d) => {
+                    if let Some(color) = d.as_ref() {
+                        write!(f, ", {}", color.describe(false))?;
+                    }
+                }
+                Color::Multi(d) => {
+                    if let Some(zones) = d.as_ref() {
+                        write!(f, ", {} zones", zones.len())?;
+                    }
+                }
+                Color::Tiles(d) => {
+                    if let Some(tiles) = d.as_ref() {
+                        write!(f, ", {} tiles", tiles.len())?;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl std::fmt::Debug for BulbInfo {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(
+                f,
+                "BulbInfo({:0>16X} - {}  ",
+                self.options.target.unwrap_or_default(),
+                self.addr
+            )?;
+
+            if let Some(name) = self.name.as_ref() {
+                write!(f, "{}", name.to_string_lossy())?;
+            }
+            if let Some(location) = self.location_label() {
+                write!(f, "/{}", location.to_string_lossy())?;
+            }
+            if let Some((vendor, product)) = self.model.as_ref() {
+                if let Some(info) = get_product_info(*vendor, *product) {
+                    write!(f, " - {} ", info.name)?;
+                    write!(f, " - {} ", product)?;
+                } else {
+                    write!(
+                        f,
+                        " - Unknown model (vendor={}, product={}) ",
+                        vendor, product
+                    )?;
+                }
+            }
+            if let Some((major, minor, build)) = self.host_firmware.as_ref() {
+                write!(f, " McuFW:{}.{}", major, minor)?;
+                write_build_time(f, *build)?;
+            }
+            if let Some((major, minor, build)) = self.wifi_firmware.as_ref() {
+                write!(f, " WifiFW:{}.{}", major, minor)?;
+                write_build_time(f, *build)?;
+            }
+            if let Some(brightness) = self.infrared.as_ref() {
+                write!(f, " IR:{}", brightness)?;
+            }
+            if let Some(on) = self.is_on() {
+                if on {
+                    write!(f, "  Powered On(")?;
+                    match self.color {
+                        Color::Unknown => write!(f, "??")?,
+                        Color::Single(ref color) => {
+                            f.write_str(
+                                &color
+                                    .as_ref()
+                                    .map(|c| c.describe(false))
+                                    .unwrap_or_else(|| "??".to_owned()),
+                            )?;
+                        }
+                        Color::Multi(ref color) => {
+                            if let Some(vec) = color.as_ref() {
+                                write!(f, "Zones: ")?;
+                                for zone in vec {
+                                    if let Some(color) = zone {
+                                        write!(f, "{} ", color.describe(true))?;
+                                    } else {
+                                        write!(f, "?? ")?;
+                                    }
+                                }
+                            }
+                        }
+                        Color::Tiles(ref tiles) => {
+                            if let Some(vec) = tiles.as_ref() {
+                                write!(f, "Tiles: {}", vec.len())?;
+                            }
+                        }
+                    }
+                    write!(f, ")")?;
+                } else {
+                    write!(f, "  Powered Off")?;
+                }
+            }
+            if let Some((vendor, product)) = self.model.as_ref() {
+                if let Some(info) = get_product_info(*vendor, *product) {
+                    if info.extended {
+                        if let Some(zones) = self.zones.as_ref() {
+                            write!(f, "(ZC:{})", zones.zones_count)?;
+                        }
+                    }
+                }
+            }
+            write!(f, ")")
+        }
+    }
+
+    /// The port LIFX devices listen on.
+    pub const LIFX_PORT: u16 = 56700;
+
+    /// Configuration used when constructing a [`Manager`].
+    #[derive(Debug, Clone)]
+    pub struct ManagerConfig {
+        /// The local address to bind the socket to.
+        pub bind_addr: SocketAddr,
+        /// Identifies this client to the bulbs.  Give each client on the LAN a different value so
+        /// they don't receive each other's replies.
+        pub source: u32,
+        /// The port discovery broadcasts are sent to.
+        pub broadcast_port: u16,
+        /// If set, discovery only broadcasts on the interfaces with these names (such as `eth0`
+        /// or `en0`), rather than every IPv4 interface.  Useful for keeping discovery off VPNs
+        /// and container bridges.
+        pub interfaces: Option<Vec<String>>,
+        /// If true, discovery is also broadcast to `255.255.255.255`, for networks that block
+        /// broadcasts to a subnet's own broadcast address.  Off by default, since some routers
+        /// drop these too.  The OS picks which interface this goes out on, so it isn't limited by
+        /// [`ManagerConfig::interfaces`].
+        pub limited_broadcast: bool,
+        /// If set, a background thread will periodically forget bulbs that haven't been heard
+        /// from in this long.  The check runs once per interval.
+        pub auto_prune: Option<Duration>,
+        /// If true, keep using the bulb table after a thread panicked while holding its lock,
+        /// instead of returning [`LifxError::LockPoisoned`].
+        pub recover_poisoned: bool,
+        /// If set, messages that aren't acknowledged in time are resent by a background thread.
+        /// Messages that are never acknowledged are reported as [`WorkerError::NoAck`].
+        pub retry: Option<RetryPolicy>,
+        /// How long the worker thread waits for a message before checking whether it should
+        /// exit.  Shorter timeouts make [`Manager::shutdown`] quicker, at the cost of more
+        /// wakeups.  Timeouts under a millisecond are rounded up.
+        pub recv_timeout: Duration,
+        /// Whether to set `SO_REUSEADDR` on the socket, so several processes can bind the same
+        /// address.  Off by default.  While it's on, binding an address that's already taken
+        /// succeeds instead of failing with [`LifxError::AddrInUse`], and the processes split
+        /// the unicast replies between them, so each only sees some of them.
+        pub reuse_addr: bool,
+        /// If set, the size of the socket's send buffer in bytes.  Otherwise the OS default is
+        /// kept.
+        pub send_buffer_size: Option<usize>,
+        /// If set, the size of the socket's receive buffer in bytes.  Otherwise the OS default is
+        /// kept.  A bigger buffer drops fewer replies when many bulbs answer a broadcast at once.
+        pub recv_buffer_size: Option<usize>,
+        /// How long cached bulb state is used before it's refreshed.  See [`MaxAges`] for the
+        /// defaults and the tradeoffs.
+        pub max_ages: MaxAges,
+        /// If set, bulbs are always sent to on this port, rather than the port they advertise in
+        /// `StateService` or reply from.  Useful when bulbs are reached through a tunnel or port
+        /// forwarding, where the port a bulb reports isn't the one that reaches it.  Discovery
+        /// still uses [`ManagerConfig::broadcast_port`].
+        pub bulb_port: Option<u16>,
+    }
+
+    /// A set of bulb states to apply together with [`Manager::apply_scene`].
+    ///
+    /// Each entry is a target, the power level and color to give it (either may be left
+    /// unchanged), and how long the transition should take in milliseconds.
+    pub type Scene = Vec<(u64, Option<PowerLevel>, Option<HSBK>, u32)>;
+
+    /// How unacknowledged messages are retransmitted.  See [`ManagerConfig::retry`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct RetryPolicy {
+        /// How many times to resend a message before giving up on it.
+        pub retries: u8,
+        /// How long to wait for an acknowledgement before resending.
+        pub timeout: Duration,
+    }
+
+    impl Default for ManagerConfig {
+        fn default() -> ManagerConfig {
+            ManagerConfig {
+                bind_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), LIFX_PORT),
+                source: 0x72757374,
+                broadcast_port: LIFX_PORT,
+                interfaces: None,
+                limited_broadcast: false,
+                auto_prune: None,
+                recover_poisoned: false,
+                retry: None,
+                recv_timeout: DEFAULT_RECV_TIMEOUT,
+                reuse_addr: false,
+                send_buffer_size: None,
+                recv_buffer_size: None,
+                max_ages: MaxAges::default(),
+                bulb_port: None,
+            }
+        }
+    }
+
+    /// Creates a blocking broadcast socket bound to `config.bind_addr`, with the socket options
+    /// from `config` applied.
+    pub(crate) fn bind_socket(config: &ManagerConfig) -> Result<UdpSocket, LifxError> {
+        let addr = config.bind_addr;
+        let sock = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        sock.set_reuse_address(config.reuse_addr)?;
+        if let Some(size) = config.send_buffer_size {
+            sock.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = config.recv_buffer_size {
+            sock.set_recv_buffer_size(size)?;
+        }
+        sock.bind(&addr.into()).map_err(|e| bind_error(addr, e))?;
+        sock.set_broadcast(true)?;
+        Ok(sock.into())
+    }
+
+    /// Converts a failure to bind `addr`, reporting [`LifxError::AddrInUse`] if the port is taken.
+    fn bind_error(addr: SocketAddr, e: io::Error) -> LifxError {
+        if e.kind() == io::ErrorKind::AddrInUse {
+            LifxError::AddrInUse(addr)
+        } else {
+            LifxError::Io(e)
+        }
+    }
+
+    /// Removes every bulb that hasn't been seen in `max_age`, returning their targets.
+    pub(crate) fn prune_bulbs(
+        bulbs: &mut HashMap<u64, BulbInfo>,
+        max_age: Duration,
+        subscribers: &Subscribers,
+    ) -> Vec<u64> {
+        let mut removed: Vec<u64> = Vec::new();
+        bulbs.retain(|target, bulb| {
+            if bulb.last_seen.elapsed() > max_age {
+                removed.push(*target);
+                false
+            } else {
+                true
+            }
+        });
+        for target in &removed {
+            subscribers.emit(BulbEvent::LostContact(*target));
+        }
+        removed
+    }
+
+    /// Makes `zones` hold exactly `count` zones, as reported by the strip at `addr`.
+    ///
+    /// Strips report a different count when they're reconfigured, and the old colors no longer
+    /// line up with the new zones, so they're all forgotten.
+    fn fit_zone_count(zones: &mut Vec<Option<HSBK>>, count: u8, addr: SocketAddr) {
+        if zones.len() == count as usize {
+            return;
+        }
+        if !zones.is_empty() {
+            info!(
+                "Strip at {} now has {} zones, not {}",
+                addr,
+                count,
+                zones.len()
+            );
+        }
+        zones.clear();
+        zones.resize(count as usize, None);
+    }
+
+    /// Applies a message received from `addr` to the matching entry in `bulbs`, creating the
+    /// entry if this is the first time we've heard from the bulb.
+    pub(crate) fn apply_message(
+        bulbs: &mut HashMap<u64, BulbInfo>,
+        shared: &SharedState,
+        raw: RawMessage,
+        addr: SocketAddr,
+    ) -> Result<(), WorkerError> {
+        let target = raw.frame_addr.target;
+        if !bulbs.contains_key(&target) {
+            shared.subscribers.emit(BulbEvent::Discovered(target));
+        }
+        let bulb = bulbs
+            .entry(target)
+            .and_modify(|bulb| bulb.update(addr))
+            .or_insert_with(|| BulbInfo::new(shared, target, addr));
+
+        // replies to other clients are addressed to their source, not ours
+        let rejected = match Message::from_raw(&raw) {
+            Ok(Message::StateUnhandled { unhandled_type }) if raw.frame.source == shared.source => {
+                Some(unhandled_type)
+            }
+            _ => None,
+        };
+
+        let old_power = bulb.power_level.data;
+        let old_colors = bulb.color_snapshot();
+        // one misbehaving bulb mustn't take down the worker, and every other bulb with it
+        let result =
+            match panic::catch_unwind(AssertUnwindSafe(|| Manager::handle_message(raw, bulb))) {
+                Ok(result) => result,
+                Err(_) => {
+                    error!(
+                        "Handling a message from {:0>16X} at {} panicked",
+                        target, addr
+                    );
+                    return Err(WorkerError::HandlerPanicked { target });
+                }
+            };
+        if bulb.power_level.data != old_power {
+            if let Some(level) = bulb.power_level.data {
+                shared
+                    .subscribers
+                    .emit(BulbEvent::PowerChanged { target, level });
+            }
+        }
+        if bulb.color_snapshot() != old_colors {
+            shared.subscribers.emit(BulbEvent::ColorChanged(target));
+        }
+        result.map_err(|error| WorkerError::Unpack { addr, error })?;
+        match rejected {
+            Some(typ) => Err(WorkerError::Rejected {
+                target,
+                error: LifxError::Unhandled(typ),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails with [`LifxError::ShortWrite`] unless `sent` bytes is the whole of an `expected`
+    /// byte packet.
+    pub(crate) fn check_sent(sent: usize, expected: usize) -> Result<(), LifxError> {
+        if sent == expected {
+            Ok(())
+        } else {
+            Err(LifxError::ShortWrite { sent, expected })
+        }
+    }
+
+    /// Sends all of `packet` to `addr`.
+    fn send_packet(sock: &dyn Transport, packet: &[u8], addr: SocketAddr) -> Result<(), LifxError> {
+        check_sent(sock.send_to(packet, addr)?, packet.len())
+    }
+
+    /// Where discovery messages go: the broadcast address of every interface in `interfaces` (or
+    /// all of them), and `255.255.255.255` too if `limited_broadcast` is set.
+    pub(crate) fn discovery_addrs(
+        port: u16,
+        interfaces: Option<&[String]>,
+        limited_broadcast: bool,
+    ) -> Result<Vec<SocketAddr>, LifxError> {
+        let mut addrs = broadcast_addrs(port, interfaces)?;
+        if limited_broadcast {
+            addrs.push(SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), port));
+        }
+        Ok(addrs)
+    }
+
+    /// Broadcasts a discovery message to each of [`discovery_addrs`].
+    fn send_discovery(
+        sock: &UdpSocket,
+        source: u32,
+        port: u16,
+        interfaces: Option<&[String]>,
+        limited_broadcast: bool,
+    ) -> Result<(), LifxError> {
+        info!("Doing discovery");
+
+        let bytes = discovery_packet(source)?;
+        for addr in discovery_addrs(port, interfaces, limited_broadcast)? {
+            debug!("Discovering bulbs on {:?}", addr);
+            send_packet(sock, &bytes, addr)?;
+        }
+        Ok(())
+    }
+
+    /// Resends every message that's waited too long for an acknowledgement, and reports the ones
+    /// that have run out of retries as [`WorkerError::NoAck`].
+    fn retransmit_all(
+        bulbs: &HashMap<u64, BulbInfo>,
+        sock: &dyn Transport,
+        policy: &RetryPolicy,
+        report: impl Fn(WorkerError),
+    ) {
+        for (&target, bulb) in bulbs.iter() {
+            match bulb.retransmit_unacknowledged(sock, policy) {
+                Ok(given_up) => given_up
+                    .into_iter()
+                    .for_each(|seq| report(WorkerError::NoAck { target, seq })),
+                Err(e) => report(WorkerError::Retransmit(e)),
+            }
+        }
+    }
+
+    /// Checks that `label` fits in a [`LifxString`], for messages that set a label.
+    fn label_cstring(label: &str) -> Result<CString, LifxError> {
+        if label.len() > MAX_LABEL_LEN {
+            return Err(LifxError::LabelTooLong(label.len()));
+        }
+        CString::new(label).map_err(|_| LifxError::LabelContainsNul)
+    }
+
+    /// Whether `actual` is within [`VERIFY_TOLERANCE`] of `expected`.
+    ///
+    /// Kelvin isn't compared, since bulbs clamp it to the range they support.  Hue and saturation
+    /// are only compared for bulbs that can show colors, and hue only for saturated colors.
+    fn colors_match(expected: HSBK, actual: HSBK, color: bool) -> bool {
+        let close = |a: u16, b: u16| a.abs_diff(b) <= VERIFY_TOLERANCE;
+        // hue wraps around, so 0 and 65535 are neighbours
+        let hue_close = expected
+            .hue
+            .wrapping_sub(actual.hue)
+            .min(actual.hue.wrapping_sub(expected.hue))
+            <= VERIFY_TOLERANCE;
+        close(expected.brightness, actual.brightness)
+            && (!color
+                || (close(expected.saturation, actual.saturation)
+                    && (expected.saturation == 0 || hue_close)))
+    }
+
+    /// Formats a bulb's target as its MAC address, the way the LIFX app shows it.
+    ///
+    /// The MAC address is the first six bytes of the target on the wire, and the target is
+    /// little-endian, so the first byte of the address is the lowest byte of the target.
+    ///
+    /// ```
+    /// # use lifx::bulb_manager::target_to_mac;
+    /// assert_eq!(target_to_mac(0x0000_5634_12d5_73d0), "d0:73:d5:12:34:56");
+    /// ```
+    pub fn target_to_mac(target: u64) -> String {
+        let bytes = target.to_le_bytes();
+        bytes[..6]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+
+    /// Parses a MAC address such as `"d0:73:d5:12:34:56"` into a target.  The inverse of
+    /// [`target_to_mac`].
+    ///
+    /// Either case is accepted.  Returns `None` unless there are exactly six hex bytes separated
+    /// by colons.
+    pub fn mac_to_target(mac: &str) -> Option<u64> {
+        let mut bytes = [0; 8];
+        let mut parts = mac.split(':');
+        for byte in &mut bytes[..6] {
+            let part = parts.next()?;
+            // from_str_radix would also accept a sign
+            if part.len() != 2 || !part.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            *byte = u8::from_str_radix(part, 16).ok()?;
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    /// A packed `GetService` broadcast, used to discover bulbs.
+    pub(crate) fn discovery_packet(source: u32) -> Result<Vec<u8>, LifxError> {
+        let opts = BuildOptions {
+            source,
+            ..Default::default()
+        };
+        let rawmsg = RawMessage::build(&opts, Message::GetService)?;
+        Ok(rawmsg.pack()?)
+    }
+
+    /// The broadcast address of every non-loopback IPv4 interface, on the given port.
+    ///
+    /// If `names` is given, only interfaces with one of those names are used.  LIFX bulbs are
+    /// only discoverable by IPv4 broadcast, so every other interface is skipped.  The reason is
+    /// logged, to help work out why no bulbs were found.  Fails if the network interfaces can't
+    /// be listed, which some sandboxes and containers don't allow.
+    pub(crate) fn broadcast_addrs(
+        port: u16,
+        names: Option<&[String]>,
+    ) -> Result<Vec<SocketAddr>, LifxError> {
+        let mut addrs: Vec<SocketAddr> = Vec::new();
+        let ifaces = get_if_addrs()?;
+        if let Some(names) = names {
+            for name in names {
+                if !ifaces.iter().any(|iface| iface.name == *name) {
+                    warn!(
+                        "No interface named {}; it won't be used for discovery",
+                        name
+                    );
+                }
+            }
+        }
+        for iface in ifaces {
+            if names.is_some_and(|names| !names.contains(&iface.name)) {
+                debug!("Skipping interface {}: it wasn't asked for", iface.name);
+                continue;
+            }
+            if iface.is_loopback() {
+                debug!(
+                    "Skipping loopback interface {} ({})",
+                    iface.name,
+                    iface.ip()
+                );
+                continue;
+            }
+            match iface.addr {
+                IfAddr::V4(Ifv4Addr {
+                    broadcast: Some(bcast),
+                    ..
+                }) => {
+                    info!(
+                        "Using interface {} ({}) for discovery",
+                        iface.name,
+                        iface.ip()
+                    );
+                    addrs.push(SocketAddr::new(IpAddr::V4(bcast), port))
+                }
+                IfAddr::V4(_) => debug!(
+                    "Skipping interface {} ({}): it has no broadcast address",
+                    iface.name,
+                    iface.ip()
+                ),
+                IfAddr::V6(_) => debug!(
+                    "Skipping interface {} ({}): LIFX discovery only works over IPv4",
+                    iface.name,
+                    iface.ip()
+                ),
+            }
+        }
+        if addrs.is_empty() {
+            warn!("No IPv4 interfaces with a broadcast address; discovery will find nothing");
+        }
+        Ok(addrs)
+    }
+
+    /// Receives a datagram if one is already waiting, returning `None` instead of blocking.
+    ///
+    /// This asks for `MSG_DONTWAIT` on the one call rather than using `set_nonblocking`, since
+    /// non-blocking mode is shared by every clone of the socket, and would make sends on other
+    /// threads fail with `WouldBlock`.
+    #[cfg(unix)]
+    fn try_recv_from(sock: &UdpSocket, buf: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+        // SAFETY: recv_from_with_flags only ever writes initialized bytes into the buffer
+        let uninit = unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) };
+        match SockRef::from(sock).recv_from_with_flags(uninit, libc::MSG_DONTWAIT) {
+            Ok((nbytes, addr)) => match addr.as_socket() {
+                Some(addr) => Ok(Some((nbytes, addr))),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "datagram from a non-IP address",
+                )),
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// There's no portable way to skip blocking on a single receive here, so the worker
+    /// applies one datagram at a time.
+    #[cfg(not(unix))]
+    fn try_recv_from(
+        _sock: &UdpSocket,
+        _buf: &mut [u8],
+    ) -> io::Result<Option<(usize, SocketAddr)>> {
+        Ok(None)
+    }
+
+    /// Counts across every known bulb, as returned by [`Manager::summary`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct NetworkSummary {
+        /// How many bulbs are known.
+        pub known: usize,
+        /// How many bulbs last reported that they were on.
+        pub on: usize,
+        /// How many bulbs haven't been heard from in the last 30 seconds, and are being checked
+        /// on.  See [`BulbInfo::online`].
+        pub stale: usize,
+        /// When the bulb heard from least recently was last seen, or `None` if no bulbs are known.
+        pub oldest_last_seen: Option<Instant>,
+    }
+
+    /// A running animation, as returned by [`Manager::animate_hue`].
+    ///
+    /// Dropping the handle also stops the animation, without waiting for it to finish.
+    #[derive(Debug)]
+    pub struct AnimationHandle {
+        stop: Sender<()>,
+        thread: JoinHandle<()>,
+    }
+
+    impl AnimationHandle {
+        /// Stops the animation, waiting for its thread to exit.  The bulb is left on whichever
+        /// color it was last sent.
+        pub fn stop(self) {
+            drop(self.stop);
+            // there is nothing useful to do here if the thread panicked
+            let _ = self.thread.join();
+        }
+    }
+
+    /// The hue for step `step` of an animation that goes once round the color wheel in `steps`
+    /// steps, starting from `start`.
+    fn animation_hue(start: u16, step: u64, steps: u32) -> u16 {
+        let offset = (step % u64::from(steps)) * 0x10000 / u64::from(steps);
+        start.wrapping_add(offset as u16)
+    }
+
+    pub struct Manager {
+        bulbs: Arc<Mutex<HashMap<u64, BulbInfo>>>,
+        /// When discovery messages were last sent.
+        last_discovery: Arc<Mutex<Instant>>,
+        pub sock: UdpSocket,
+        shared: SharedState,
+        errors: Receiver<WorkerError>,
+        alive: Arc<AtomicBool>,
+        /// Set to ask the worker thread to exit.
+        stop: Arc<AtomicBool>,
+        worker: Option<JoinHandle<()>>,
+        recover_poisoned: bool,
+        broadcast_port: u16,
+        interfaces: Option<Vec<String>>,
+        limited_broadcast: bool,
+        /// Reports errors from threads started after construction.
+        error_tx: SyncSender<WorkerError>,
+        /// Hanging up stops the periodic discovery thread, if there is one.
+        periodic_discovery: Mutex<Option<(Sender<()>, JoinHandle<()>)>>,
+        /// Set while the thread sending coalesced commands is running.
+        flushing: Arc<AtomicBool>,
+    }
+
+    impl Manager {
+        pub fn new() -> Result<Manager, LifxError> {
+            Self::with_config(ManagerConfig::default())
+        }
+
+        /// Creates a manager with the default config, that retransmits messages which aren't
+        /// acknowledged within `timeout`, up to `retries` times.
+        pub fn with_retry(retries: u8, timeout: Duration) -> Result<Manager, LifxError> {
+            Self::with_config(ManagerConfig {
+                retry: Some(RetryPolicy { retries, timeout }),
+                ..ManagerConfig::default()
+            })
+        }
+
+        pub fn with_config(config: ManagerConfig) -> Result<Manager, LifxError> {
+            let sock: UdpSocket = bind_socket(&config)?;
+
+            // spawn a thread that can send to our socket
+            let recv_sock: UdpSocket = sock.try_clone()?;
+            // wake up now and then, so the worker notices when it's asked to stop
+            // (set_read_timeout rejects a zero timeout)
+            recv_sock.set_read_timeout(Some(config.recv_timeout.max(Duration::from_millis(1))))?;
+
+            let bulbs: Arc<Mutex<HashMap<u64, BulbInfo>>> = Arc::new(Mutex::new(HashMap::new()));
+            let receiver_bulbs: Arc<Mutex<HashMap<u64, BulbInfo>>> = bulbs.clone();
+            let shared: SharedState = SharedState::from_config(&config);
+            let worker_shared: SharedState = shared.clone();
+            let (error_tx, errors) = sync_channel(WORKER_ERROR_BACKLOG);
+            let retry_error_tx: SyncSender<WorkerError> = error_tx.clone();
+            let discovery_error_tx: SyncSender<WorkerError> = error_tx.clone();
+            let alive: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
+            let worker_alive: Arc<AtomicBool> = alive.clone();
+            let stop: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+            let worker_stop: Arc<AtomicBool> = stop.clone();
+            let probe: Vec<u8> = discovery_packet(config.source)?;
+
+            // spawn a thread that will receive data from our socket and update our internal data structures
+            let worker = spawn(move || {
+                Self::worker(
+                    recv_sock,
+                    receiver_bulbs,
+                    worker_shared,
+                    error_tx,
+                    worker_stop,
+                    probe,
+                );
+                worker_alive.store(false, Ordering::SeqCst);
+            });
+
+            if let Some(max_age) = config.auto_prune {
+                // only hold a weak reference, so this thread exits once the bulb table is dropped
+                let prune_bulbs_ref = Arc::downgrade(&bulbs);
+                let subscribers: Subscribers = shared.subscribers.clone();
+                spawn(move || loop {
+                    sleep(max_age);
+                    match prune_bulbs_ref.upgrade() {
+                        Some(bulbs) => {
+                            let mut bulbs = bulbs.lock().unwrap_or_else(PoisonError::into_inner);
+                            prune_bulbs(&mut bulbs, max_age, &subscribers);
+                        }
+                        None => break,
+                    }
+                });
+            }
+
+            if let Some(policy) = config.retry {
+                let retry_bulbs_ref = Arc::downgrade(&bulbs);
+                let retry_sock: UdpSocket = sock.try_clone()?;
+                let poll_interval = (policy.timeout / 2).max(Duration::from_millis(10));
+                spawn(move || loop {
+                    sleep(poll_interval);
+                    let Some(bulbs) = retry_bulbs_ref.upgrade() else {
+                        break;
+                    };
+                    let bulbs = bulbs.lock().unwrap_or_else(PoisonError::into_inner);
+                    retransmit_all(&bulbs, &retry_sock, &policy, |error| {
+                        let _ = retry_error_tx.try_send(error);
+                    });
+                });
+            }
+
+            let mgr: Manager = Manager {
+                bulbs,
+                last_discovery: Arc::new(Mutex::new(Instant::now())),
+                sock,
+                shared,
+                errors,
+                alive,
+                stop,
+                worker: Some(worker),
+                recover_poisoned: config.recover_poisoned,
+                broadcast_port: config.broadcast_port,
+                interfaces: config.interfaces,
+                limited_broadcast: config.limited_broadcast,
+                error_tx: discovery_error_tx,
+                periodic_discovery: Mutex::new(None),
+                flushing: Arc::new(AtomicBool::new(false)),
+            };
+            Ok(mgr)
+        }
+
+        pub fn handle_message(
+            raw: RawMessage,
+            bulb: &mut BulbInfo,
+        ) -> Result<(), lifx_core::Error> {
+            match Message::from_raw(&raw)? {
+                Message::StateService { port, service } => {
+                    match bulb
+                        .services
+                        .iter_mut()
+                        .find(|(known, _)| *known == service)
+                    {
+                        Some(entry) => entry.1 = port,
+                        None => bulb.services.push((service, port)),
+                    }
+                    match (service, u16::try_from(port)) {
+                        (Service::UDP, Ok(port)) => {
+                            bulb.addr.set_port(bulb.bulb_port.unwrap_or(port))
+                        }
+                        (Service::UDP, Err(_)) => {
+                            warn!("Ignoring invalid UDP port {} from {}", port, bulb.addr)
+                        }
+                        _ => info!(
+                            "Ignoring unsupported service {:?}/{} from {}",
+                            service, port, bulb.addr
+                        ),
+                    }
+                }
+                Message::StateLabel { label } => {
+                    bulb.set_name(label.cstr().to_owned(), LabelSource::StateLabel)
+                }
+                Message::StateLocation {
+                    location,
+                    label,
+                    updated_at,
+                } => {
+                    bulb.location.update((label.cstr().to_owned(), updated_at));
+                    bulb.location_id = Some(location.0);
+                }
+                Message::StateGroup {
+                    label, updated_at, ..
+                } => bulb.group.update((label.cstr().to_owned(), updated_at)),
+                Message::StateVersion {
+                    vendor, product, ..
+                } => {
+                    bulb.model.update((vendor, product));
+                    if let Some(info) = get_product_info(vendor, product) {
+                        if info.relays {
+                            // switches have no light to ask the color of
+                        } else if info.matrix {
+                            bulb.color = Color::Tiles(RefreshableData::empty(
+                                bulb.max_ages.color,
+                                Message::GetDeviceChain,
+                            ))
+                        } else if info.multizone {
+                            bulb.color = Color::Multi(RefreshableData::empty(
+                                bulb.max_ages.color,
+                                Message::GetColorZones {
+                                    start_index: 0,
+                                    end_index: 255,
+                                },
+                            ))
+                        } else {
+                            bulb.color = Color::Single(RefreshableData::empty(
+                                bulb.max_ages.color,
+                                Message::LightGet,
+                            ))
+                        }
+                    }
+                }
+                Message::StatePower { level } => bulb.power_level.update(level),
+                Message::LightStateInfrared { brightness } => bulb.infrared.update(brightness),
+                Message::LightStateHevCycle { remaining, .. } => bulb.hev_cycle.update(remaining),
+                Message::RelayStatePower { relay_index, level } => {
+                    match bulb.relays.get_mut(relay_index as usize) {
+                        Some(relay) => relay.update(level),
+                        None => {
+                            debug!("Power for unknown relay {} from {}", relay_index, bulb.addr)
+                        }
+                    }
+                }
+                Message::EchoResponse { payload } => {
+                    let pending = bulb
+                        .pending_echo
+                        .get_mut()
+                        .unwrap_or_else(PoisonError::into_inner);
+                    match *pending {
+                        Some((sent, at)) if sent == payload.0 => {
+                            bulb.last_rtt = Some(at.elapsed());
+                            *pending = None;
+                        }
+                        _ => debug!("Ignoring unexpected echo response from {}", bulb.addr),
+                    }
+                }
+                Message::StateHostFirmware {
+                    build,
+                    version_minor,
+                    version_major,
+                    ..
+                } => bulb
+                    .host_firmware
+                    .update((version_major, version_minor, build)),
+                Message::StateWifiFirmware {
+                    build,
+                    version_minor,
+                    version_major,
+                    ..
+                } => bulb
+                    .wifi_firmware
+                    .update((version_major, version_minor, build)),
+                Message::StateWifiInfo { signal, .. } => bulb.wifi_signal.update(signal),
+                Message::LightState {
+                    color,
+                    power,
+                    label,
+                    ..
+                } => {
+                    if let Color::Single(ref mut d) = bulb.color {
+                        d.update(color);
+                        bulb.power_level.update(power);
+                    }
+                    // a LightState sent before a rename can arrive after the new StateLabel
+                    if bulb.name.data.is_none() {
+                        bulb.set_name(label.cstr().to_owned(), LabelSource::LightState);
+                    }
+                }
+                Message::StateDeviceChain {
+                    start_index,
+                    tile_devices,
+                    tile_devices_count,
+                } => {
+                    if let Color::Tiles(ref mut d) = bulb.color {
+                        let mut tiles = d.data.take().unwrap_or_default();
+                        let count = (tile_devices_count as usize).min(tile_devices.len());
+                        tiles.truncate(start_index as usize + count);
+                        for (offset, info) in tile_devices[..count].iter().enumerate() {
+                            let tile = Tile {
+                                width: info.width,
+                                height: info.height,
+                                colors: None,
+                            };
+                            match tiles.get_mut(start_index as usize + offset) {
+                                Some(known) => {
+                                    // keep the pixels we know about, unless the tile was swapped
+                                    if (known.width, known.height) != (tile.width, tile.height) {
+                                        *known = tile;
+                                    }
+                                }
+                                None => tiles.push(tile),
+                            }
+                        }
+                        d.update(tiles);
+                    }
+                }
+                Message::StateTileState64 {
+                    tile_index, colors, ..
+                } => {
+                    if let Color::Tiles(ref mut d) = bulb.color {
+                        match d.data.as_mut().and_then(|t| t.get_mut(tile_index as usize)) {
+                            Some(tile) => tile.colors = Some(colors),
+                            None => {
+                                debug!("Pixels for unknown tile {} from {}", tile_index, bulb.addr)
+                            }
+                        }
+                    }
+                }
+                Message::StateZone {
+                    count,
+                    index,
+                    color,
+                } => {
+                    if let Color::Multi(ref mut d) = bulb.color {
+                        let v = d.data.get_or_insert_with(Vec::new);
+                        fit_zone_count(v, count, bulb.addr);
+                        match v.get_mut(index as usize) {
+                            // leave unchanged zones alone so listeners only hear about real changes
+                            Some(zone) if *zone != Some(color) => *zone = Some(color),
+                            Some(_) => (),
+                            None => warn!(
+                                "Skipped zone past the end of {} (index={}, count={})",
+                                bulb.addr, index, count
+                            ),
+                        }
+                    }
+                }
+                Message::StateMultiZone {
+                    count,
+                    index,
+                    color0,
+                    color1,
+                    color2,
+                    color3,
+                    color4,
+                    color5,
+                    color6,
+                    color7,
+                } => {
+                    if let Color::Multi(ref mut d) = bulb.color {
+                        let v = d.data.get_or_insert_with(Vec::new);
+                        fit_zone_count(v, count, bulb.addr);
+                        let colors = [
+                            color0, color1, color2, color3, color4, color5, color6, color7,
+                        ];
+                        let mut skipped = 0;
+                        for (offset, color) in colors.into_iter().enumerate() {
+                            match v.get_mut(index as usize + offset) {
+                                Some(zone) => *zone = Some(color),
+                                None => skipped += 1,
+                            }
+                        }
+                        if skipped > 0 {
+                            warn!(
+                                "Skipped {} zones past the end of {} (index={}, count={})",
+                                skipped, bulb.addr, index, count
+                            );
+                        }
+                    }
+                }
+                Message::StateExtendedColorZones {
+                    zones_count,
+                    zone_index,
+                    colors_count,
+                    colors,
+                } => {
+                    // don't trust the counts, or a buggy strip could leave us writing past the
+                    // end of its zones later
+                    let available = (zones_count as usize).saturating_sub(zone_index as usize);
+                    let count = (colors_count as usize)
+                        .min(colors.len())
+                        .min(EXTENDED_ZONES_PER_MESSAGE)
+                        .min(available);
+                    if count < colors_count as usize {
+                        warn!(
+                            "Ignoring {} zones past the end of {} (index={}, colors={}, count={})",
+                            colors_count as usize - count,
+                            bulb.addr,
+                            zone_index,
+                            colors_count,
+                            zones_count
+                        );
+                    }
+                    let mut zones = bulb.zones.data.take().unwrap_or(Zones {
+                        zones_count,
+                        colors: Vec::new(),
+                        zone_index,
+                        colors_count,
+                    });
+                    zones.merge(zones_count, zone_index, &colors[..count]);
+                    bulb.zones.update(zones);
+                }
+                Message::Acknowledgement { seq } => {
+                    let outstanding = bulb
+                        .outstanding
+                        .get_mut()
+                        .unwrap_or_else(PoisonError::into_inner);
+                    match outstanding.remove(&seq) {
+                        Some(pending) if pending.zones => bulb.last_zone_ack = Some(Instant::now()),
+                        Some(_) => {}
+                        None => debug!("Unexpected ack for sequence {} from {}", seq, bulb.addr),
+                    }
+                }
+                Message::StateUnhandled { unhandled_type } => {
+                    warn!(
+                        "Bulb at {} doesn't support message type {}",
+                        bulb.addr, unhandled_type
+                    );
+                }
+                unknown => {
+                    debug!("Received, but ignored {:?}", unknown);
+                }
             }
-            if let Some((major, minor)) = self.host_firmware.as_ref() {
-                write!(f, " McuFW:{}.{}", major, minor)?;
+            Ok(())
+        }
+
+        /// Receives datagrams from `recv_sock` and applies them to `receiver_bulbs`.
+        ///
+        /// Non-fatal errors are reported over `errors` and the loop keeps going.  This only
+        /// returns once the socket can no longer be read from.
+        fn worker(
+            recv_sock: UdpSocket,
+            receiver_bulbs: Arc<Mutex<HashMap<u64, BulbInfo>>>,
+            shared: SharedState,
+            errors: SyncSender<WorkerError>,
+            stop: Arc<AtomicBool>,
+            probe: Vec<u8>,
+        ) {
+            // if nobody is draining the channel, drop the error rather than blocking the worker
+            let report = |e: WorkerError| {
+                let _ = errors.try_send(e);
+            };
+            let mut buf = [0; 1024];
+            let mut last_probe_check = Instant::now();
+            let mut batch: Vec<(RawMessage, SocketAddr)> = Vec::with_capacity(WORKER_BATCH_SIZE);
+            while !stop.load(Ordering::SeqCst) {
+                if last_probe_check.elapsed() >= PROBE_CHECK_INTERVAL {
+                    last_probe_check = Instant::now();
+                    let mut bulbs = receiver_bulbs
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner);
+                    for (&target, bulb) in bulbs.iter_mut() {
+                        if let Err(error) = bulb.probe_if_quiet(&recv_sock, &probe) {
+                            report(WorkerError::Probe { target, error });
+                        }
+                    }
+                }
+                if !Self::receive_batch(&recv_sock, &mut buf, &mut batch, &shared, &report) {
+                    return;
+                }
+                Self::apply_batch(&receiver_bulbs, &shared, &mut batch, &report);
             }
-            if let Some((major, minor)) = self.wifi_firmware.as_ref() {
-                write!(f, " WifiFW:{}.{}", major, minor)?;
+        }
+
+        /// Waits for a datagram, then adds it and up to [`WORKER_BATCH_SIZE`] in total of any
+        /// others already waiting to `batch`.
+        ///
+        /// During a discovery burst many replies arrive at once, and collecting them first means
+        /// the bulb table is locked once for the lot.  Returns false if the socket can no longer
+        /// be read from.
+        fn receive_batch(
+            recv_sock: &UdpSocket,
+            buf: &mut [u8],
+            batch: &mut Vec<(RawMessage, SocketAddr)>,
+            shared: &SharedState,
+            report: &impl Fn(WorkerError),
+        ) -> bool {
+            let mut add = |data: &[u8], addr: SocketAddr| {
+                if let Some(raw) = Self::unpack_datagram(data, addr, report) {
+                    shared.raw_hook.call(&raw, addr);
+                    batch.push((raw, addr));
+                }
+            };
+            match recv_sock.recv_from(buf) {
+                Ok((nbytes, addr)) => add(&buf[..nbytes], addr),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                            | io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    return true
+                }
+                Err(e) => {
+                    let error = WorkerError::Recv(e);
+                    let fatal = error.is_fatal();
+                    report(error);
+                    return !fatal;
+                }
             }
-            if let Some(level) = self.power_level.as_ref() {
-                if *level > 0 {
-                    write!(f, "  Powered On(")?;
-                    match self.color {
-                        Color::Unknown => write!(f, "??")?,
-                        Color::Single(ref color) => {
-                            f.write_str(
-                                &color
-                                    .as_ref()
-                                    .map(|c| c.describe(false))
-                                    .unwrap_or_else(|| "??".to_owned()),
-                            )?;
+            for _ in 1..WORKER_BATCH_SIZE {
+                match try_recv_from(recv_sock, buf) {
+                    Ok(Some((nbytes, addr))) => add(&buf[..nbytes], addr),
+                    // real errors will show up again on the next blocking receive
+                    Ok(None) | Err(_) => break,
+                }
+            }
+            true
+        }
+
+        /// Applies everything in `batch` under a single lock of the bulb table, leaving it
+        /// empty.
+        fn apply_batch(
+            receiver_bulbs: &Mutex<HashMap<u64, BulbInfo>>,
+            shared: &SharedState,
+            batch: &mut Vec<(RawMessage, SocketAddr)>,
+            report: &impl Fn(WorkerError),
+        ) {
+            if batch.is_empty() {
+                return;
+            }
+            let mut bulbs = match receiver_bulbs.lock() {
+                Ok(bulbs) => bulbs,
+                Err(poisoned) => {
+                    report(WorkerError::LockPoisoned);
+                    poisoned.into_inner()
+                }
+            };
+            for (raw, addr) in batch.drain(..) {
+                if let Err(e) = apply_message(&mut bulbs, shared, raw, addr) {
+                    report(e);
+                }
+            }
+        }
+
+        /// Decodes a datagram for the worker, returning `None` if there's nothing to apply.
+        ///
+        /// Anything with the wrong protocol number fails to unpack, and anything whose length
+        /// doesn't match its frame header is logged and dropped, so stray traffic from other
+        /// devices on the port can't be mistaken for a bulb.
+        pub(crate) fn unpack_datagram(
+            data: &[u8],
+            addr: SocketAddr,
+            report: &impl Fn(WorkerError),
+        ) -> Option<RawMessage> {
+            if data.is_empty() {
+                debug!("Received a zero-byte datagram from {:?}", addr);
+                return None;
+            }
+            match RawMessage::unpack(data) {
+                Ok(raw) if raw.frame.size as usize != data.len() => {
+                    debug!(
+                        "Discarding datagram from {:?}: {} bytes, but the frame says {}",
+                        addr,
+                        data.len(),
+                        raw.frame.size
+                    );
+                    None
+                }
+                Ok(raw) if raw.frame_addr.target == 0 => None,
+                Ok(raw) => Some(raw),
+                Err(error) => {
+                    report(WorkerError::Unpack { addr, error });
+                    None
+                }
+            }
+        }
+
+        /// Errors reported by the background worker thread.
+        pub fn errors(&self) -> &Receiver<WorkerError> {
+            &self.errors
+        }
+
+        /// The source identifier stamped on every message this manager sends.
+        ///
+        /// Bulbs echo it back in their replies, which makes it handy for filtering packet captures.
+        pub fn source(&self) -> u32 {
+            self.shared.source
+        }
+
+        /// Whether the background worker thread is still receiving messages.
+        pub fn is_alive(&self) -> bool {
+            self.alive.load(Ordering::SeqCst)
+        }
+
+        /// Stops the background worker thread, waiting for it to exit.
+        ///
+        /// Dropping the manager does the same thing; this just makes it explicit.
+        pub fn shutdown(mut self) {
+            self.stop_worker();
+        }
+
+        fn stop_worker(&mut self) {
+            self.stop_periodic_discovery();
+            self.stop.store(true, Ordering::SeqCst);
+            if let Some(worker) = self.worker.take() {
+                // there is nothing useful to do here if the worker panicked
+                let _ = worker.join();
+            }
+        }
+
+        /// When discovery messages were last sent, by [`Manager::discover`] or
+        /// [`Manager::discover_unicast`], or when the manager was created if they never have been.
+        pub fn last_discovery(&self) -> Instant {
+            *self
+                .last_discovery
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+        }
+
+        /// How long ago discovery messages were last sent.  See [`Manager::last_discovery`].
+        pub fn time_since_discovery(&self) -> Duration {
+            self.last_discovery().elapsed()
+        }
+
+        /// Whether it's been at least `interval` since discovery messages were last sent.
+        ///
+        /// This suits an application that already has its own main loop, and can rediscover
+        /// whenever this returns true:
+        ///
+        /// ```no_run
+        /// # use lifx::bulb_manager::Manager;
+        /// # use std::time::Duration;
+        /// # let mgr = Manager::new().unwrap();
+        /// loop {
+        ///     if mgr.should_rediscover(Duration::from_secs(300)) {
+        ///         mgr.discover().unwrap();
+        ///     }
+        ///     // ... the rest of the application's work
+        /// #   break;
+        /// }
+        /// ```
+        ///
+        /// Otherwise, [`Manager::start_periodic_discovery`] does this on a background thread.
+        pub fn should_rediscover(&self, interval: Duration) -> bool {
+            self.time_since_discovery() >= interval
+        }
+
+        pub fn discover(&self) -> Result<(), LifxError> {
+            send_discovery(
+                &self.sock,
+                self.shared.source,
+                self.broadcast_port,
+                self.interfaces.as_deref(),
+                self.limited_broadcast,
+            )?;
+            *self
+                .last_discovery
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Instant::now();
+            Ok(())
+        }
+
+        /// Starts a background thread that calls [`Manager::discover`] every `interval`, so bulbs
+        /// that are switched on later show up without anyone asking.
+        ///
+        /// This replaces any periodic discovery started earlier.  It runs until
+        /// [`Manager::stop_periodic_discovery`] is called or the manager shuts down, and the bulb
+        /// table isn't locked while it broadcasts.  Failures are reported as
+        /// [`WorkerError::Discovery`].
+        pub fn start_periodic_discovery(&self, interval: Duration) -> Result<(), LifxError> {
+            self.stop_periodic_discovery();
+            let sock: UdpSocket = self.sock.try_clone()?;
+            let source = self.shared.source;
+            let port = self.broadcast_port;
+            let interfaces = self.interfaces.clone();
+            let limited_broadcast = self.limited_broadcast;
+            let last_discovery = self.last_discovery.clone();
+            let error_tx = self.error_tx.clone();
+            let (stop_tx, stop_rx) = channel::<()>();
+            let thread = spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+                    let result = send_discovery(
+                        &sock,
+                        source,
+                        port,
+                        interfaces.as_deref(),
+                        limited_broadcast,
+                    );
+                    match result {
+                        Ok(()) => {
+                            *last_discovery
+                                .lock()
+                                .unwrap_or_else(PoisonError::into_inner) = Instant::now()
                         }
-                        Color::Multi(ref color) => {
-                            if let Some(vec) = color.as_ref() {
-                                write!(f, "Zones: ")?;
-                                for zone in vec {
-                                    if let Some(color) = zone {
-                                        write!(f, "{} ", color.describe(true))?;
-                                    } else {
-                                        write!(f, "?? ")?;
-                                    }
-                                }
-                            }
+                        // if nobody is draining the channel, drop the error rather than block
+                        Err(e) => {
+                            let _ = error_tx.try_send(WorkerError::Discovery(e));
                         }
                     }
-                    write!(f, ")")?;
-                } else {
-                    write!(f, "  Powered Off")?;
+                }
+            });
+            *self
+                .periodic_discovery
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Some((stop_tx, thread));
+            Ok(())
+        }
+
+        /// Stops the thread started by [`Manager::start_periodic_discovery`], waiting for it to
+        /// exit.  Does nothing if periodic discovery isn't running.
+        pub fn stop_periodic_discovery(&self) {
+            let running = self
+                .periodic_discovery
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take();
+            if let Some((stop_tx, thread)) = running {
+                drop(stop_tx);
+                // there is nothing useful to do here if the thread panicked
+                let _ = thread.join();
+            }
+        }
+
+        /// Broadcasts a discovery message and waits for the replies to settle.
+        ///
+        /// Returns once no new bulb has appeared for `quiet_period`, or once `timeout` has
+        /// elapsed, with the targets of every bulb heard from during the wait.
+        pub fn discover_and_wait(
+            &self,
+            quiet_period: Duration,
+            timeout: Duration,
+        ) -> Result<Vec<u64>, LifxError> {
+            let start = Instant::now();
+            self.discover()?;
+
+            let poll_interval = quiet_period.min(Duration::from_millis(50));
+            let mut last_change = Instant::now();
+            let mut seen: Vec<u64> = Vec::new();
+            loop {
+                sleep(poll_interval);
+                let mut responded: Vec<u64> = self
+                    .lock_bulbs()?
+                    .iter()
+                    .filter(|(_, bulb)| bulb.last_seen >= start)
+                    .map(|(target, _)| *target)
+                    .collect();
+                responded.sort_unstable();
+                if responded != seen {
+                    seen = responded;
+                    last_change = Instant::now();
+                }
+                if last_change.elapsed() >= quiet_period || start.elapsed() >= timeout {
+                    return Ok(seen);
                 }
             }
-            if let Some((vendor, product)) = self.model.as_ref() {
-                if let Some(info) = get_product_info(*vendor, *product) {
-                    if info.extended {
-                        if let Some(zones) = self.zones.as_ref() {
-                            write!(
-                                f,
-                                "(ZC:{}, ZI:{}, ZCC:{})",
-                                zones.zones_count, zones.zone_index, zones.colors_count
-                            )?;
+        }
+
+        /// Sets every known bulb to `color`.
+        ///
+        /// A failure to reach one bulb doesn't stop the others from being updated; instead, the
+        /// target and error of each bulb that couldn't be updated is returned.
+        pub fn set_color_all(
+            &self,
+            color: HSBK,
+            duration: u32,
+        ) -> Result<Vec<(u64, LifxError)>, LifxError> {
+            let bulbs = self.lock_bulbs()?;
+            Ok(bulbs
+                .iter()
+                .filter_map(|(target, bulb)| {
+                    let result = bulb.set_bulb_color(&self.sock, color, duration);
+                    result.err().map(|e| (*target, e))
+                })
+                .collect())
+        }
+
+        /// Sets the power level of every known bulb, fading over `duration` milliseconds.
+        ///
+        /// Failures are collected the same way as [`Manager::set_color_all`].
+        pub fn set_power_all(
+            &self,
+            level: u16,
+            duration: u32,
+        ) -> Result<Vec<(u64, LifxError)>, LifxError> {
+            let bulbs = self.lock_bulbs()?;
+            Ok(bulbs
+                .iter()
+                .filter_map(|(target, bulb)| {
+                    let result = bulb.set_power_duration(&self.sock, level, duration);
+                    result.err().map(|e| (*target, e))
+                })
+                .collect())
+        }
+
+        /// Applies every entry of `scene`.
+        ///
+        /// All the color changes are sent before any of the power changes, so that bulbs given
+        /// the same duration fade together.  Failures are collected the same way as
+        /// [`Manager::set_color_all`]; a bulb whose color couldn't be set isn't powered on or off.
+        pub fn apply_scene(&self, scene: &Scene) -> Result<Vec<(u64, LifxError)>, LifxError> {
+            let bulbs = self.lock_bulbs()?;
+            let mut failed: Vec<(u64, LifxError)> = Vec::new();
+            for &(target, _, color, duration) in scene {
+                let Some(bulb) = bulbs.get(&target) else {
+                    failed.push((target, LifxError::BulbNotFound(target)));
+                    continue;
+                };
+                if let Some(color) = color {
+                    if let Err(e) = bulb.set_bulb_color(&self.sock, color, duration) {
+                        failed.push((target, e));
+                    }
+                }
+            }
+            for &(target, power, _, duration) in scene {
+                let (Some(level), Some(bulb)) = (power, bulbs.get(&target)) else {
+                    continue;
+                };
+                if failed.iter().any(|(t, _)| *t == target) {
+                    continue;
+                }
+                if let Err(e) = bulb.set_power_duration(&self.sock, level as u16, duration) {
+                    failed.push((target, e));
+                }
+            }
+            Ok(failed)
+        }
+
+        /// Calls `f` with every known bulb.
+        ///
+        /// The bulb table is locked while `f` runs, so `f` shouldn't block for long.
+        pub fn for_each_bulb<F: FnMut(&BulbInfo)>(&self, f: F) -> Result<(), LifxError> {
+            let bulbs = self.lock_bulbs()?;
+            bulbs.values().for_each(f);
+            Ok(())
+        }
+
+        /// Sets the color of the bulb with the given target, then asks for its color until it
+        /// reports the new one.
+        ///
+        /// Polling starts once the `duration` millisecond fade should have finished, and gives up
+        /// with [`LifxError::VerifyFailed`] if the color hasn't matched `timeout` after that.  The
+        /// reported color has to be within about 1% of `color` on each channel, except kelvin,
+        /// which bulbs clamp to the range they support.  Fails with [`LifxError::Unsupported`]
+        /// for strips and chains, which don't have a single color to check.
+        pub fn set_color_verified(
+            &self,
+            target: u64,
+            color: HSBK,
+            duration: u32,
+            timeout: Duration,
+        ) -> Result<(), LifxError> {
+            let supports_color = self.with_bulb(target, |bulb| {
+                if !matches!(bulb.color, Color::Single(_)) {
+                    return Err(LifxError::Unsupported);
+                }
+                bulb.set_bulb_color(&self.sock, color, duration)?;
+                Ok(bulb.supports_color())
+            })??;
+            sleep(Duration::from_millis(duration.into()));
+
+            let start = Instant::now();
+            let poll_interval = timeout.min(Duration::from_millis(50));
+            let mut next_get = start;
+            let mut asked_at = None;
+            loop {
+                let reported = self.with_bulb(target, |bulb| match &bulb.color {
+                    Color::Single(d) => d.data.map(|data| (data, d.last_updated)),
+                    _ => None,
+                })?;
+                if let (Some((actual, updated)), Some(asked_at)) = (reported, asked_at) {
+                    if updated >= asked_at && colors_match(color, actual, supports_color) {
+                        return Ok(());
+                    }
+                }
+                if start.elapsed() >= timeout {
+                    return Err(LifxError::VerifyFailed(target));
+                }
+                if Instant::now() >= next_get {
+                    let now = Instant::now();
+                    // failures here just mean we'll try again on the next round
+                    if let Ok(Ok(())) =
+                        self.with_bulb(target, |bulb| bulb.send(&self.sock, Message::LightGet))
+                    {
+                        asked_at.get_or_insert(now);
+                    }
+                    next_get = Instant::now() + WAIT_FOR_COLOR_REFRESH_INTERVAL;
+                }
+                sleep(poll_interval);
+            }
+        }
+
+        /// Starts a background thread that cycles a bulb's hue round the color wheel once every
+        /// `period`, in `steps` evenly spaced steps, until the returned handle is stopped or
+        /// dropped.
+        ///
+        /// The animation starts from the bulb's cached hue, keeping its brightness and kelvin, at
+        /// full saturation if the bulb was white.  Each step fades into the next, so a few steps
+        /// per second is enough to look smooth.  Steps are unacknowledged, and a step that would
+        /// exceed the bulb's rate limit is skipped rather than sent late, so the animation keeps
+        /// to `period`.  Other failures are reported as [`WorkerError::Animation`].  The
+        /// animation ends by itself if the bulb is forgotten or the manager is dropped.
+        ///
+        /// Fails with [`LifxError::Unsupported`] for strips and chains, which don't have a single
+        /// color to animate.
+        pub fn animate_hue(
+            &self,
+            target: u64,
+            period: Duration,
+            steps: u32,
+        ) -> Result<AnimationHandle, LifxError> {
+            let steps = steps.max(1);
+            let mut color = self.with_bulb(target, |bulb| {
+                if !matches!(bulb.color, Color::Single(_)) {
+                    return Err(LifxError::Unsupported);
+                }
+                Ok(bulb.base_color())
+            })??;
+            if color.saturation == 0 {
+                color.saturation = u16::MAX;
+            }
+            let start_hue = color.hue;
+            let interval = period / steps;
+            let fade = u32::try_from(interval.as_millis()).unwrap_or(u32::MAX);
+
+            let bulbs_ref = Arc::downgrade(&self.bulbs);
+            let sock: UdpSocket = self.sock.try_clone()?;
+            let error_tx = self.error_tx.clone();
+            let (stop_tx, stop_rx) = channel::<()>();
+            let thread = spawn(move || {
+                // sleep until each step is due, rather than for a fixed interval after sending,
+                // so time spent sending doesn't slow the animation down
+                let mut next_step = Instant::now();
+                for step in 0u64.. {
+                    let Some(bulbs) = bulbs_ref.upgrade() else {
+                        break;
+                    };
+                    color.hue = animation_hue(start_hue, step, steps);
+                    let result = {
+                        let bulbs = bulbs.lock().unwrap_or_else(PoisonError::into_inner);
+                        let Some(bulb) = bulbs.get(&target) else {
+                            break;
+                        };
+                        bulb.set_bulb_color_unacked(&sock, color, fade)
+                    };
+                    drop(bulbs);
+                    match result {
+                        Ok(()) | Err(LifxError::RateLimited) => {}
+                        // if nobody is draining the channel, drop the error rather than block
+                        Err(error) => {
+                            let _ = error_tx.try_send(WorkerError::Animation { target, error });
+                        }
+                    }
+                    next_step += interval;
+                    let wait = next_step.saturating_duration_since(Instant::now());
+                    if let Err(RecvTimeoutError::Disconnected) | Ok(()) = stop_rx.recv_timeout(wait)
+                    {
+                        break;
+                    }
+                }
+            });
+            Ok(AnimationHandle {
+                stop: stop_tx,
+                thread,
+            })
+        }
+
+        /// Calls `f` with the bulb with the given target, returning its result.
+        ///
+        /// The bulb table is locked while `f` runs, so `f` shouldn't block for long.
+        pub fn with_bulb<R>(
+            &self,
+            target: u64,
+            f: impl FnOnce(&BulbInfo) -> R,
+        ) -> Result<R, LifxError> {
+            let bulbs = self.lock_bulbs()?;
+            let bulb = bulbs.get(&target).ok_or(LifxError::BulbNotFound(target))?;
+            Ok(f(bulb))
+        }
+
+        /// Reboots every known bulb.  See [`BulbInfo::force_reboot`].
+        ///
+        /// Failures are collected the same way as [`Manager::set_color_all`], so one bulb that
+        /// can't be reached doesn't stop the rest from rebooting.
+        pub fn force_reboot_all(&self) -> Result<Vec<(u64, LifxError)>, LifxError> {
+            let bulbs = self.lock_bulbs()?;
+            Ok(bulbs
+                .iter()
+                .filter_map(|(target, bulb)| {
+                    let result = bulb.force_reboot(&self.sock);
+                    result.err().map(|e| (*target, e))
+                })
+                .collect())
+        }
+
+        /// Locks the bulb table, honoring [`ManagerConfig::recover_poisoned`].
+        fn lock_bulbs(&self) -> Result<MutexGuard<'_, HashMap<u64, BulbInfo>>, LifxError> {
+            match self.bulbs.lock() {
+                Ok(bulbs) => Ok(bulbs),
+                Err(poisoned) if self.recover_poisoned => Ok(poisoned.into_inner()),
+                Err(_) => Err(LifxError::LockPoisoned),
+            }
+        }
+
+        /// Limits how many messages per second are sent to each bulb.  Zero disables limiting.
+        ///
+        /// Sends that would exceed the limit fail with [`LifxError::RateLimited`].  The default is
+        /// 20, as recommended by LIFX.
+        pub fn set_rate_limit(&self, msgs_per_sec: u32) {
+            self.shared
+                .rate_limit
+                .store(msgs_per_sec, Ordering::Relaxed);
+        }
+
+        /// Turns command coalescing on or off.  It's off by default.
+        ///
+        /// While it's on, a color or power command that would exceed a bulb's rate limit is
+        /// queued instead of failing with [`LifxError::RateLimited`], and replaces any command of
+        /// the same kind already queued for that bulb.  Queued commands are sent as soon as the
+        /// rate limit allows, so the last command sent always arrives eventually, but the ones in
+        /// between may be skipped.  Color and power commands are still sent in the order they were
+        /// given, so a new command waits behind any older ones that are queued.  Other messages
+        /// aren't held back, and may overtake queued commands.  This suits things like sliders,
+        /// which send far more updates than a bulb can keep up with.  Errors sending a queued
+        /// command are reported as [`WorkerError::Flush`].
+        ///
+        /// Queued commands are sent by a background thread, which is started when coalescing is
+        /// turned on, and exits once it's turned off and the queues are empty.
+        pub fn enable_coalescing(&self, enabled: bool) -> Result<(), LifxError> {
+            self.shared.coalesce.store(enabled, Ordering::SeqCst);
+            if !enabled || self.flushing.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            let sock: UdpSocket = self.sock.try_clone()?;
+            if self.flushing.swap(true, Ordering::SeqCst) {
+                // another call started the thread first
+                return Ok(());
+            }
+            let bulbs_ref = Arc::downgrade(&self.bulbs);
+            let coalesce: Arc<AtomicBool> = self.shared.coalesce.clone();
+            let flushing: Arc<AtomicBool> = self.flushing.clone();
+            let error_tx = self.error_tx.clone();
+            spawn(move || {
+                let mut left_over = false;
+                loop {
+                    sleep(COALESCE_FLUSH_INTERVAL);
+                    // keep going after coalescing is turned off, until the queues are empty
+                    if !left_over && !coalesce.load(Ordering::SeqCst) {
+                        flushing.store(false, Ordering::SeqCst);
+                        // coalescing may have been turned back on by a caller that still saw
+                        // this thread running, in which case it's up to us to carry on
+                        if !coalesce.load(Ordering::SeqCst) || flushing.swap(true, Ordering::SeqCst)
+                        {
+                            break;
+                        }
+                        continue;
+                    }
+                    let Some(bulbs) = bulbs_ref.upgrade() else {
+                        flushing.store(false, Ordering::SeqCst);
+                        break;
+                    };
+                    left_over = false;
+                    let bulbs = bulbs.lock().unwrap_or_else(PoisonError::into_inner);
+                    for (&target, bulb) in bulbs.iter() {
+                        match bulb.flush_coalesced(&sock) {
+                            Ok(pending) => left_over |= pending,
+                            Err(error) => {
+                                left_over = true;
+                                let _ = error_tx.try_send(WorkerError::Flush { target, error });
+                            }
                         }
                     }
                 }
+            });
+            Ok(())
+        }
+
+        /// Makes each bulb remember the last `len` messages sent to it, for
+        /// [`BulbInfo::command_history`].  Zero, the default, turns the log off.
+        ///
+        /// Existing logs are trimmed to the new length the next time each bulb is sent something.
+        pub fn set_command_log_len(&self, len: usize) {
+            self.shared.command_log_len.store(len, Ordering::Relaxed);
+        }
+
+        pub fn refresh(&self) -> Result<(), LifxError> {
+            let bulbs = self.lock_bulbs()?;
+            for bulb in bulbs.values() {
+                match bulb.query_for_missing_info(&self.sock) {
+                    // whatever didn't get sent is still stale, and will be retried next time
+                    Ok(()) | Err(LifxError::RateLimited) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+
+        /// Like [`Manager::refresh`], but only queries the bulb with the given target.
+        ///
+        /// Unlike `refresh`, running into the rate limit is reported as
+        /// [`LifxError::RateLimited`].
+        pub fn refresh_bulb(&self, target: u64) -> Result<(), LifxError> {
+            let bulbs = self.lock_bulbs()?;
+            let bulb = bulbs.get(&target).ok_or(LifxError::BulbNotFound(target))?;
+            bulb.query_for_missing_info(&self.sock)
+        }
+
+        /// Sends `msg` to the bulb with the given target, for messages this crate has no wrapper
+        /// for yet.
+        ///
+        /// The message goes through the same rate limiting, sequence numbering and retransmission
+        /// as every other command.  Fails with [`LifxError::BulbNotFound`] if the bulb isn't
+        /// known.
+        pub fn send_message(&self, target: u64, msg: Message) -> Result<(), LifxError> {
+            let bulbs = self.lock_bulbs()?;
+            let bulb = bulbs.get(&target).ok_or(LifxError::BulbNotFound(target))?;
+            bulb.send(&self.sock, msg)
+        }
+
+        /// Moves the bulb with the given target to another location.  See
+        /// [`BulbInfo::set_location`].
+        pub fn set_location(
+            &self,
+            target: u64,
+            location_id: [u8; 16],
+            label: &str,
+            updated_at: u64,
+        ) -> Result<(), LifxError> {
+            let mut bulbs = self.lock_bulbs()?;
+            let bulb = bulbs
+                .get_mut(&target)
+                .ok_or(LifxError::BulbNotFound(target))?;
+            bulb.set_location(&self.sock, location_id, label, updated_at)
+        }
+
+        /// Whether the strip with the given target is ready for the next frame of an animation.
+        /// See [`BulbInfo::zone_frame_ready`].
+        ///
+        /// Fails with [`LifxError::BulbNotFound`] if the bulb isn't known.
+        pub fn next_frame_ready(&self, target: u64, timeout: Duration) -> Result<bool, LifxError> {
+            let bulbs = self.lock_bulbs()?;
+            let bulb = bulbs.get(&target).ok_or(LifxError::BulbNotFound(target))?;
+            Ok(bulb.zone_frame_ready(timeout))
+        }
+
+        /// Renames the bulb with the given target.  See [`BulbInfo::set_label`].
+        pub fn set_label(&self, target: u64, label: &str) -> Result<(), LifxError> {
+            let mut bulbs = self.lock_bulbs()?;
+            let bulb = bulbs
+                .get_mut(&target)
+                .ok_or(LifxError::BulbNotFound(target))?;
+            bulb.set_label(&self.sock, label)
+        }
+
+        pub fn add_bulb(&self, addr: SocketAddr) -> Result<(), LifxError> {
+            let bytes = discovery_packet(self.shared.source)?;
+            info!("Attempting connection to: {:?}", addr);
+            send_packet(&self.sock, &bytes, addr)?;
+            Ok(())
+        }
+
+        /// Waits up to `timeout` for the color of the bulb with the given target to be known.
+        ///
+        /// A newly discovered bulb has to report its model before we know how to ask for its
+        /// color, so this keeps refreshing the bulb until both have arrived.  Returns `None` if
+        /// the color still isn't known when the timeout expires.
+        pub fn wait_for_color(&self, target: u64, timeout: Duration) -> Option<SnapshotColor> {
+            let start = Instant::now();
+            let poll_interval = timeout.min(Duration::from_millis(50));
+            let mut next_refresh = start;
+            loop {
+                if let Some(color) = self
+                    .with_bulb(target, BulbInfo::snapshot_color)
+                    .ok()
+                    .flatten()
+                {
+                    return Some(color);
+                }
+                if start.elapsed() >= timeout {
+                    return None;
+                }
+                if Instant::now() >= next_refresh {
+                    // failures here just mean we'll try again on the next round
+                    let _ = self.refresh_bulb(target);
+                    next_refresh = Instant::now() + WAIT_FOR_COLOR_REFRESH_INTERVAL;
+                }
+                sleep(poll_interval);
+            }
+        }
+
+        /// Like [`Manager::add_bulb`], but waits up to `timeout` for the bulb to answer.
+        ///
+        /// Returns the target of the bulb at `addr`, or [`LifxError::NoResponse`] if nothing
+        /// replied in time.
+        pub fn add_bulb_blocking(
+            &self,
+            addr: SocketAddr,
+            timeout: Duration,
+        ) -> Result<u64, LifxError> {
+            let start = Instant::now();
+            self.add_bulb(addr)?;
+
+            let poll_interval = timeout.min(Duration::from_millis(50));
+            loop {
+                sleep(poll_interval);
+                // match on the IP alone, as the stored port is replaced by the advertised port or
+                // `ManagerConfig::bulb_port`
+                let found = self
+                    .lock_bulbs()?
+                    .iter()
+                    .find(|(_, bulb)| {
+                        bulb.last_seen >= start
+                            && bulb.addresses().iter().any(|known| known.ip() == addr.ip())
+                    })
+                    .map(|(target, _)| *target);
+                if let Some(target) = found {
+                    return Ok(target);
+                }
+                if start.elapsed() >= timeout {
+                    return Err(LifxError::NoResponse(addr));
+                }
+            }
+        }
+
+        /// Sends a discovery message directly to each of `addrs`, rather than broadcasting.
+        ///
+        /// This works across subnets and VLANs where broadcasts don't reach the bulbs.  Addresses
+        /// that haven't answered after a short delay are retried a couple of times, so this
+        /// blocks for up to a second.  Pair with [`Manager::known_addrs`] to reconnect to
+        /// previously seen bulbs after a restart.
+        pub fn discover_unicast(&self, addrs: &[SocketAddr]) -> Result<(), LifxError> {
+            let bytes = discovery_packet(self.shared.source)?;
+            let mut pending: Vec<SocketAddr> = addrs.to_vec();
+            for attempt in 0..UNICAST_DISCOVERY_ATTEMPTS {
+                if attempt > 0 {
+                    sleep(UNICAST_DISCOVERY_RETRY_DELAY);
+                    let known = self.known_addrs()?;
+                    pending.retain(|addr| !known.contains(addr));
+                    if pending.is_empty() {
+                        break;
+                    }
+                }
+                for addr in &pending {
+                    debug!("Discovering bulb at {:?}", addr);
+                    send_packet(&self.sock, &bytes, *addr)?;
+                }
+            }
+            *self
+                .last_discovery
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Instant::now();
+            Ok(())
+        }
+
+        /// The addresses of every bulb we currently know about.
+        ///
+        /// These can be saved and later handed to [`Manager::discover_unicast`].
+        pub fn known_addrs(&self) -> Result<Vec<SocketAddr>, LifxError> {
+            let bulbs = self.lock_bulbs()?;
+            Ok(bulbs.values().map(|bulb| bulb.addr).collect())
+        }
+
+        /// Writes the target, address and label of every known bulb to `path`, as JSON.
+        ///
+        /// Load them again with [`Manager::load_known_bulbs`].
+        #[cfg(feature = "serde")]
+        pub fn save_known_bulbs(&self, path: impl AsRef<Path>) -> Result<(), LifxError> {
+            let known: Vec<KnownBulb> = {
+                let bulbs = self.lock_bulbs()?;
+                bulbs
+                    .iter()
+                    .map(|(&target, bulb)| KnownBulb {
+                        target,
+                        addr: bulb.addr,
+                        label: bulb.name.as_ref().map(|s| s.to_string_lossy().into_owned()),
+                    })
+                    .collect()
+            };
+            let file = BufWriter::new(File::create(path)?);
+            serde_json::to_writer_pretty(file, &known)?;
+            Ok(())
+        }
+
+        /// Adds the bulbs saved by [`Manager::save_known_bulbs`] to the bulb table, and sends
+        /// each of them a discovery message to check it's still there.
+        ///
+        /// Bulbs that are already known are left alone.  A saved bulb that doesn't answer is
+        /// marked offline like any other quiet bulb; see [`BulbInfo::online`].
+        #[cfg(feature = "serde")]
+        pub fn load_known_bulbs(&self, path: impl AsRef<Path>) -> Result<(), LifxError> {
+            let file = BufReader::new(File::open(path)?);
+            let known: Vec<KnownBulb> = serde_json::from_reader(file)?;
+            let bytes = discovery_packet(self.shared.source)?;
+            let mut bulbs = self.lock_bulbs()?;
+            for saved in known {
+                if bulbs.contains_key(&saved.target) {
+                    continue;
+                }
+                let mut bulb = BulbInfo::new(&self.shared, saved.target, saved.addr);
+                if let Some(label) = saved.label.and_then(|label| CString::new(label).ok()) {
+                    bulb.set_name(label, LabelSource::Saved);
+                }
+                debug!("Checking saved bulb at {:?}", saved.addr);
+                send_packet(&self.sock, &bytes, saved.addr)?;
+                bulbs.insert(saved.target, bulb);
+            }
+            Ok(())
+        }
+
+        /// Counts how many bulbs are known, on, and stale, all from one snapshot of the bulb table.
+        pub fn summary(&self) -> Result<NetworkSummary, LifxError> {
+            let bulbs = self.lock_bulbs()?;
+            let now = Instant::now();
+            Ok(NetworkSummary {
+                known: bulbs.len(),
+                on: bulbs.values().filter(|b| b.is_on() == Some(true)).count(),
+                stale: bulbs
+                    .values()
+                    .filter(|b| now.duration_since(b.last_seen) >= QUIET_AFTER)
+                    .count(),
+                oldest_last_seen: bulbs.values().map(|b| b.last_seen).min(),
+            })
+        }
+
+        /// Copies the cached state of every known bulb, all from one snapshot of the bulb table.
+        ///
+        /// The bulbs are sorted by target.  The copies are owned, so they can be handed to another
+        /// thread without holding up the worker.  See [`BulbInfo::snapshot`].
+        pub fn snapshot_all(&self) -> Result<Vec<BulbSnapshot>, LifxError> {
+            let bulbs = self.lock_bulbs()?;
+            let mut snapshots: Vec<(u64, BulbSnapshot)> = bulbs
+                .iter()
+                .map(|(&target, bulb)| (target, bulb.snapshot()))
+                .collect();
+            drop(bulbs);
+            snapshots.sort_by_key(|(target, _)| *target);
+            Ok(snapshots
+                .into_iter()
+                .map(|(_, snapshot)| snapshot)
+                .collect())
+        }
+
+        /// Forgets every bulb that hasn't been heard from in `max_age`.
+        ///
+        /// Returns the targets of the bulbs that were removed.
+        pub fn prune_stale(&self, max_age: Duration) -> Result<Vec<u64>, LifxError> {
+            let mut bulbs = self.lock_bulbs()?;
+            Ok(prune_bulbs(&mut bulbs, max_age, &self.shared.subscribers))
+        }
+
+        /// Forgets the bulb with the given target straight away, returning its entry if it was
+        /// known.
+        ///
+        /// A bulb that's still on the network is added back the next time it replies to
+        /// anything, such as a discovery broadcast.
+        pub fn remove_bulb(&self, target: u64) -> Result<Option<BulbInfo>, LifxError> {
+            let removed = self.lock_bulbs()?.remove(&target);
+            if removed.is_some() {
+                self.shared.subscribers.emit(BulbEvent::LostContact(target));
             }
-            write!(f, ")")
+            Ok(removed)
+        }
+
+        /// Returns a channel that receives a [`BulbEvent`] whenever a bulb's state changes.
+        ///
+        /// Every call returns a new receiver, and each receiver gets its own copy of every event.
+        ///
+        /// This is a [`std::sync::mpsc::Receiver`], like [`Manager::errors`], so the crate doesn't
+        /// need another channel dependency.  That means it can't be used with `select!`, and it
+        /// isn't `Sync`, so move it to the thread that reads it rather than sharing it.  Events
+        /// are queued without limit until they're received, so drop receivers you're done with.
+        pub fn subscribe(&self) -> Receiver<BulbEvent> {
+            self.shared.subscribers.subscribe()
+        }
+
+        /// Registers `callback` to be called with every message received from a bulb, before the
+        /// manager handles it itself.  This replaces any callback registered earlier.
+        ///
+        /// The callback runs on the worker thread, so it should be cheap: nothing else is
+        /// received while it runs.  The bulb table isn't locked while it runs, but calling
+        /// `on_raw_message` from inside the callback will deadlock.
+        pub fn on_raw_message(&self, callback: RawMessageCallback) {
+            self.shared.raw_hook.set(callback);
+        }
+
+        /// Returns the target of a bulb whose label is `label`.
+        ///
+        /// Bulbs that haven't reported their label yet are skipped.  If several bulbs share the
+        /// same label, which one is returned is unspecified; see [`Manager::find_by_label_all`].
+        pub fn find_by_label(&self, label: &str) -> Result<Option<u64>, LifxError> {
+            Ok(self.find_by_label_all(label)?.into_iter().next())
+        }
+
+        /// Returns the targets of every bulb whose label is `label`.
+        pub fn find_by_label_all(&self, label: &str) -> Result<Vec<u64>, LifxError> {
+            let bulbs = self.lock_bulbs()?;
+            Ok(bulbs
+                .iter()
+                .filter(|(_, bulb)| {
+                    bulb.name
+                        .as_ref()
+                        .is_some_and(|name| name.to_string_lossy() == label)
+                })
+                .map(|(target, _)| *target)
+                .collect())
         }
     }
 
-    pub struct Manager {
-        pub bulbs: Arc<Mutex<HashMap<u64, BulbInfo>>>,
-        pub last_discovery: Instant,
-        pub sock: UdpSocket,
-        source: u32,
+    impl Drop for Manager {
+        fn drop(&mut self) {
+            self.stop_worker();
+        }
     }
 
-    impl Manager {
-        pub fn new() -> Result<Manager, failure::Error> {
-            let sock: UdpSocket = UdpSocket::bind("0.0.0.0:56700")?;
-            sock.set_broadcast(true)?;
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::transport::MockTransport;
+        use std::cell::RefCell;
+        use std::sync::mpsc::TryRecvError;
 
-            // spawn a thread that can send to our socket
-            let recv_sock: UdpSocket = sock.try_clone()?;
+        fn test_addr() -> SocketAddr {
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), LIFX_PORT)
+        }
 
-            let bulbs: Arc<Mutex<HashMap<u64, BulbInfo>>> = Arc::new(Mutex::new(HashMap::new()));
-            let receiver_bulbs: Arc<Mutex<HashMap<u64, BulbInfo>>> = bulbs.clone();
-            let source: u32 = 0x72757374;
+        /// A bulb with target 1 at [`test_addr`], which hasn't reported anything yet.
+        fn test_bulb() -> BulbInfo {
+            test_bulb_with(&SharedState::new(1))
+        }
 
-            // spawn a thread that will receive data from our socket and update our internal data structures
-            spawn(move || Self::worker(recv_sock, source, receiver_bulbs));
+        fn test_bulb_with(shared: &SharedState) -> BulbInfo {
+            BulbInfo::new(shared, 1, test_addr())
+        }
 
-            let mgr: Manager = Manager {
-                bulbs,
-                last_discovery: Instant::now(),
-                sock,
-                source,
+        /// Handles `msg` as though `bulb` had sent it.
+        fn deliver(bulb: &mut BulbInfo, msg: Message) {
+            let raw = RawMessage::build(&BuildOptions::default(), msg).unwrap();
+            Manager::handle_message(raw, bulb).unwrap();
+        }
+
+        /// Acknowledges the message `bulb` was sent with sequence number `seq`.
+        fn deliver_ack(bulb: &mut BulbInfo, seq: u8) {
+            // acks carry the sequence number in the header, not the payload
+            let options = BuildOptions {
+                sequence: seq,
+                ..BuildOptions::default()
             };
-            Ok(mgr)
+            let raw = RawMessage::build(&options, Message::Acknowledgement { seq }).unwrap();
+            Manager::handle_message(raw, bulb).unwrap();
         }
 
-        pub fn handle_message(
-            raw: RawMessage,
-            bulb: &mut BulbInfo,
-        ) -> Result<(), lifx_core::Error> {
-            match Message::from_raw(&raw)? {
-                Message::StateService { port, service } => {
-                    if port != bulb.addr.port() as u32 || service != Service::UDP {
-                        println!("Unsupported service: {:?}/{}", service, port);
-                    }
-                }
-                Message::StateLabel { label } => bulb.name.update(label.cstr().to_owned()),
-                Message::StateLocation { label, .. } => {
-                    bulb.location.update(label.cstr().to_owned())
-                }
-                Message::StateVersion {
-                    vendor, product, ..
-                } => {
-                    bulb.model.update((vendor, product));
-                    if let Some(info) = get_product_info(vendor, product) {
-                        if info.multizone {
-                            bulb.color = Color::Multi(RefreshableData::empty(
-                                Duration::from_secs(15),
-                                Message::GetColorZones {
-                                    start_index: 0,
-                                    end_index: 255,
-                                },
-                            ))
-                        } else {
-                            bulb.color = Color::Single(RefreshableData::empty(
-                                Duration::from_secs(15),
-                                Message::LightGet,
-                            ))
-                        }
-                    }
-                }
-                Message::StatePower { level } => bulb.power_level.update(level),
-                Message::StateHostFirmware {
-                    version_minor,
-                    version_major,
-                    ..
-                } => bulb.host_firmware.update((version_major, version_minor)),
-                Message::StateWifiFirmware {
-                    version_minor,
-                    version_major,
-                    ..
-                } => bulb.wifi_firmware.update((version_major, version_minor)),
-                Message::LightState {
-                    color,
-                    power,
-                    label,
-                    ..
-                } => {
-                    if let Color::Single(ref mut d) = bulb.color {
-                        d.update(color);
-                        bulb.power_level.update(power);
-                    }
-                    bulb.name.update(label.cstr().to_owned());
-                }
-                Message::StateZone {
-                    count,
-                    index,
-                    color,
-                } => {
-                    if let Color::Multi(ref mut d) = bulb.color {
-                        d.data.get_or_insert_with(|| {
-                            let mut v = Vec::with_capacity(count as usize);
-                            v.resize(count as usize, None);
-                            assert!(index <= count);
-                            v
-                        })[index as usize] = Some(color);
-                    }
-                }
-                Message::StateMultiZone {
-                    count,
-                    index,
-                    color0,
-                    color1,
-                    color2,
-                    color3,
-                    color4,
-                    color5,
-                    color6,
-                    color7,
-                } => {
-                    if let Color::Multi(ref mut d) = bulb.color {
-                        let v = d.data.get_or_insert_with(|| {
-                            let mut v = Vec::with_capacity(count as usize);
-                            v.resize(count as usize, None);
-                            assert!(index + 7 <= count);
-                            v
-                        });
-
-                        v[index as usize] = Some(color0);
-                        v[index as usize + 1] = Some(color1);
-                        v[index as usize + 2] = Some(color2);
-                        v[index as usize + 3] = Some(color3);
-                        v[index as usize + 4] = Some(color4);
-                        v[index as usize + 5] = Some(color5);
-                        v[index as usize + 6] = Some(color6);
-                        v[index as usize + 7] = Some(color7);
-                    }
-                }
-                Message::StateExtendedColorZones {
-                    zones_count,
+        /// Tells `bulb` which LIFX product it is, such as 27 for an A19 or 38 for a Beam.
+        fn set_product(bulb: &mut BulbInfo, product: u32) {
+            let version = Message::StateVersion {
+                vendor: 1,
+                product,
+                reserved: 0,
+            };
+            deliver(bulb, version);
+        }
+
+        fn multi_zone(count: u8, index: u8, color: HSBK) -> Message {
+            Message::StateMultiZone {
+                count,
+                index,
+                color0: color,
+                color1: color,
+                color2: color,
+                color3: color,
+                color4: color,
+                color5: color,
+                color6: color,
+                color7: color,
+            }
+        }
+
+        #[test]
+        fn test_zone_count_change() {
+            let mut bulb = test_bulb();
+            bulb.color = Color::Multi(RefreshableData::empty(
+                Duration::from_secs(15),
+                Message::GetColorZones {
+                    start_index: 0,
+                    end_index: 255,
+                },
+            ));
+            let red = HsbkBuilder::new().saturation_percent(100.0).build();
+
+            for index in [0, 8] {
+                deliver(&mut bulb, multi_zone(16, index, red));
+            }
+            assert_eq!(bulb.current_zone_colors(), Some(vec![red; 16]));
+            assert!(bulb.zones_complete());
+
+            // the old colors don't belong to any of the new zones
+            deliver(&mut bulb, multi_zone(32, 16, BLACK));
+            let Color::Multi(d) = &bulb.color else {
+                panic!("strip is no longer multizone");
+            };
+            let zones = d.as_ref().unwrap();
+            assert_eq!(zones.len(), 32);
+            assert!(zones[..16].iter().all(Option::is_none));
+            assert!(zones[16..24].iter().all(|zone| *zone == Some(BLACK)));
+            assert!(zones[24..].iter().all(Option::is_none));
+            assert!(!bulb.zones_complete());
+
+            // and shrinking works the same way
+            deliver(&mut bulb, multi_zone(8, 0, red));
+            assert_eq!(bulb.current_zone_colors(), Some(vec![red; 8]));
+
+            // as do extended reports, where zones that haven't been reported are black
+            let blue = HsbkBuilder::new()
+                .hue_degrees(240.0)
+                .saturation_percent(100.0)
+                .build();
+            let extended = |zones_count, colors_count, color| Message::StateExtendedColorZones {
+                zones_count,
+                zone_index: 0,
+                colors_count,
+                colors: Box::new([color; EXTENDED_ZONES_PER_MESSAGE]),
+            };
+            deliver(&mut bulb, extended(16, 16, red));
+            assert_eq!(bulb.get_colors().unwrap(), [red; 16]);
+            deliver(&mut bulb, extended(32, 8, blue));
+            let colors = bulb.get_colors().unwrap();
+            assert_eq!(colors.len(), 32);
+            assert_eq!(colors[..8], [blue; 8]);
+            assert!(colors[8..].iter().all(|color| *color == BLACK));
+            deliver(&mut bulb, extended(16, 4, red));
+            let colors = bulb.get_colors().unwrap();
+            assert_eq!(colors.len(), 16);
+            assert_eq!(colors[..4], [red; 4]);
+            assert!(colors[4..].iter().all(|color| *color == BLACK));
+        }
+
+        #[test]
+        fn test_mac_round_trip() {
+            let target = 0x0000_5634_12d5_73d0;
+            assert_eq!(target_to_mac(target), "d0:73:d5:12:34:56");
+            assert_eq!(mac_to_target("d0:73:d5:12:34:56"), Some(target));
+            assert_eq!(mac_to_target("D0:73:D5:12:34:56"), Some(target));
+            for bad in [
+                "",
+                "d0:73:d5:12:34",
+                "d0:73:d5:12:34:56:78",
+                "d0:73:d5:12:34:5g",
+            ] {
+                assert_eq!(mac_to_target(bad), None, "{:?}", bad);
+            }
+            assert_eq!(mac_to_target("d0:73:d5:12:34:+5"), None);
+        }
+
+        #[test]
+        fn test_bulb_state() {
+            let mut bulb = test_bulb();
+            let empty = bulb.state();
+            assert_eq!(empty.label, None);
+            assert_eq!(empty.color, None);
+
+            set_product(&mut bulb, 27);
+            let mut handle = |msg: Message| {
+                deliver(&mut bulb, msg);
+                bulb.state()
+            };
+            let red = HsbkBuilder::new().saturation_percent(100.0).build();
+            let on = handle(Message::LightState {
+                color: red,
+                reserved: 0,
+                power: 65535,
+                label: LifxString::new(&CString::new("Desk").unwrap()),
+                reserved2: 0,
+            });
+            assert_eq!(on.label.as_deref(), Some(c"Desk"));
+            assert_eq!(on.model, Some((1, 27)));
+            assert_eq!(on.power_level, Some(65535));
+            assert_eq!(on.color, Some(SnapshotColor::Single(red)));
+
+            // only the power level differs after the bulb is switched off
+            let off = handle(Message::StatePower { level: 0 });
+            assert_ne!(on, off);
+            assert_eq!(
+                BulbState {
+                    power_level: Some(0),
+                    ..on
+                },
+                off
+            );
+        }
+
+        #[test]
+        fn test_label_sources() {
+            let mut bulb = test_bulb();
+            let light_state = |label: &str| Message::LightState {
+                color: BLACK,
+                reserved: 0,
+                power: 0,
+                label: LifxString::new(&CString::new(label).unwrap()),
+                reserved2: 0,
+            };
+            let state_label = |label: &str| Message::StateLabel {
+                label: LifxString::new(&CString::new(label).unwrap()),
+            };
+            let mut handle = |msg: Message| {
+                deliver(&mut bulb, msg);
+                let name = bulb.name.as_ref().unwrap().to_str().unwrap().to_owned();
+                (name, bulb.name_source().unwrap())
+            };
+
+            // with nothing better, the label from LightState is used
+            assert_eq!(
+                handle(light_state("Old")),
+                ("Old".to_owned(), LabelSource::LightState)
+            );
+            // a rename is reported, but a LightState from before it is still in flight
+            assert_eq!(
+                handle(state_label("New")),
+                ("New".to_owned(), LabelSource::StateLabel)
+            );
+            assert_eq!(
+                handle(light_state("Old")),
+                ("New".to_owned(), LabelSource::StateLabel)
+            );
+            assert_eq!(
+                handle(state_label("New")),
+                ("New".to_owned(), LabelSource::StateLabel)
+            );
+        }
+
+        #[test]
+        fn test_colors_match() {
+            let red = HsbkBuilder::new().saturation_percent(100.0).build();
+            let almost_red = HSBK {
+                hue: 65535 - 100,
+                brightness: red.brightness - 100,
+                kelvin: 9000,
+                ..red
+            };
+            assert!(colors_match(red, almost_red, true));
+            let orange = HsbkBuilder::new()
+                .hue_degrees(30.0)
+                .saturation_percent(100.0)
+                .build();
+            assert!(!colors_match(red, orange, true));
+            // white bulbs only have brightness to compare
+            assert!(colors_match(red, orange, false));
+            let dim = HSBK {
+                brightness: red.brightness / 2,
+                ..red
+            };
+            assert!(!colors_match(red, dim, false));
+        }
+
+        #[test]
+        fn test_bulb_port() {
+            let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+            let service = |port| Message::StateService {
+                service: Service::UDP,
+                port,
+            };
+
+            // the advertised port is used, even for replies from another port
+            let shared = SharedState::new(1);
+            let mut bulb = BulbInfo::new(&shared, 1, SocketAddr::new(ip, 40000));
+            deliver(&mut bulb, service(56701));
+            assert_eq!(bulb.addr, SocketAddr::new(ip, 56701));
+            bulb.update(SocketAddr::new(ip, 40000));
+            assert_eq!(bulb.addr, SocketAddr::new(ip, 56701));
+
+            // a fixed port overrides both
+            let shared = SharedState {
+                bulb_port: Some(50000),
+                ..SharedState::new(1)
+            };
+            let mut bulb = BulbInfo::new(&shared, 1, SocketAddr::new(ip, 40000));
+            assert_eq!(bulb.addr, SocketAddr::new(ip, 50000));
+            deliver(&mut bulb, service(56701));
+            bulb.update(SocketAddr::new(ip, 40000));
+            assert_eq!(bulb.addresses(), [SocketAddr::new(ip, 50000)]);
+        }
+
+        #[test]
+        fn test_max_ages() {
+            let max_ages = MaxAges {
+                color: Duration::from_secs(60),
+                ..MaxAges::default()
+            };
+            let shared = SharedState {
+                max_ages,
+                ..SharedState::new(1)
+            };
+            let mut bulb = test_bulb_with(&shared);
+            assert_eq!(bulb.name.max_age, HOUR);
+            assert_eq!(bulb.zones.max_age, max_ages.color);
+
+            // the color cache is only created once the model is known
+            set_product(&mut bulb, 27);
+            let Color::Single(d) = &bulb.color else {
+                panic!("bulb has no single color");
+            };
+            assert_eq!(d.max_age, max_ages.color);
+        }
+
+        #[test]
+        fn test_command_history() {
+            let shared = SharedState::new(1);
+            let bulb = test_bulb_with(&shared);
+            let mock = MockTransport::new();
+            bulb.set_power(&mock, PowerLevel::Enabled).unwrap();
+            assert!(bulb.command_history().is_empty());
+
+            shared.command_log_len.store(2, Ordering::Relaxed);
+            bulb.set_power(&mock, PowerLevel::Enabled).unwrap();
+            bulb.set_bulb_color(&mock, BLACK, 0).unwrap();
+            bulb.set_power(&mock, PowerLevel::Standby).unwrap();
+            let names: Vec<&str> = bulb.command_history().iter().map(|(_, n)| *n).collect();
+            assert_eq!(names, ["LightSetColor", "SetPower"]);
+        }
+
+        #[test]
+        fn test_animation_hue() {
+            assert_eq!(animation_hue(0, 0, 4), 0);
+            assert_eq!(animation_hue(0, 1, 4), 0x4000);
+            assert_eq!(animation_hue(0, 3, 4), 0xC000);
+            assert_eq!(animation_hue(0, 4, 4), 0);
+            // wraps round past red
+            assert_eq!(animation_hue(0xC000, 2, 4), 0x4000);
+            assert_eq!(animation_hue(1234, 5, 1), 1234);
+            assert_eq!(animation_hue(0, 1, 3), 21845);
+        }
+
+        #[test]
+        fn test_inconsistent_extended_zones() {
+            let mut bulb = test_bulb();
+            set_product(&mut bulb, 38);
+            let red = HsbkBuilder::new().saturation_percent(100.0).build();
+            let mut report = |zone_index, colors_count| {
+                let msg = Message::StateExtendedColorZones {
+                    zones_count: 10,
                     zone_index,
                     colors_count,
-                    colors,
-                } => {
-                    bulb.zones.update(Zones {
-                        zones_count: zones_count,
-                        zone_index: zone_index,
-                        colors_count: colors_count,
-                        colors: colors,
-                    });
-                    // if let Some(zones) = bulb.zones.as_ref() {
-                    //     println!("state: {:?}", zones.colors);
-                    // }
-                }
-                Message::Acknowledgement { seq } => {
-                    bulb.options.sequence = (seq % 255) + 1;
-                    //println!("Awk: {} {}", bulb.addr, bulb.options.sequence);
-                }
-                unknown => {
-                    println!("Received, but ignored {:?}", unknown);
-                }
+                    colors: Box::new([red; EXTENDED_ZONES_PER_MESSAGE]),
+                };
+                deliver(&mut bulb, msg);
+                let zones = bulb.zones.as_ref().unwrap();
+                (zones.zone_index(), zones.colors_count())
+            };
+
+            // more colors than fit in a message, or on the strip
+            assert_eq!(report(0, 255), (0, 10));
+            // runs past the end of the strip
+            assert_eq!(report(5, 82), (5, 5));
+            // starts past the end of the strip
+            assert_eq!(report(20, 1), (20, 0));
+
+            let zones = bulb.zones.as_ref().unwrap();
+            assert_eq!(zones.zones_count, 10);
+            assert_eq!(bulb.get_colors().unwrap(), [red; 10]);
+
+            // writes are still limited to the real length of the strip
+            let mock = MockTransport::new();
+            bulb.set_strip_array(&mock, &[red; 10], 0, 0, ApplicationRequest::Apply)
+                .unwrap();
+            assert!(matches!(
+                bulb.set_strip_array(&mock, &[red; 11], 0, 0, ApplicationRequest::Apply),
+                Err(LifxError::InvalidZoneCount { .. })
+            ));
+        }
+
+        #[test]
+        fn test_add_bulb_blocking_with_bulb_port() {
+            let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+            let mgr = Manager::with_config(ManagerConfig {
+                bind_addr: localhost,
+                bulb_port: Some(50000),
+                ..ManagerConfig::default()
+            })
+            .unwrap();
+
+            // answer the discovery probe like a bulb would
+            let bulb_sock = UdpSocket::bind(localhost).unwrap();
+            bulb_sock
+                .set_read_timeout(Some(Duration::from_secs(1)))
+                .unwrap();
+            let bulb_addr = bulb_sock.local_addr().unwrap();
+            let responder = spawn(move || {
+                let mut buf = [0; 1024];
+                let (_, from) = bulb_sock.recv_from(&mut buf).unwrap();
+                let options = BuildOptions {
+                    target: Some(0xd073d5),
+                    ..BuildOptions::default()
+                };
+                let msg = Message::StateService {
+                    service: Service::UDP,
+                    port: bulb_addr.port().into(),
+                };
+                let packet = RawMessage::build(&options, msg).unwrap().pack().unwrap();
+                bulb_sock.send_to(&packet, from).unwrap();
+            });
+
+            let target = mgr
+                .add_bulb_blocking(bulb_addr, Duration::from_secs(1))
+                .unwrap();
+            responder.join().unwrap();
+            assert_eq!(target, 0xd073d5);
+            let addr = mgr.with_bulb(target, |bulb| bulb.addr).unwrap();
+            assert_eq!(addr, SocketAddr::new(bulb_addr.ip(), 50000));
+        }
+
+        #[test]
+        fn test_addr_in_use() {
+            let first = bind_socket(&ManagerConfig {
+                bind_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+                ..ManagerConfig::default()
+            })
+            .unwrap();
+            let addr = first.local_addr().unwrap();
+            let config = ManagerConfig {
+                bind_addr: addr,
+                ..ManagerConfig::default()
+            };
+            match bind_socket(&config) {
+                Err(LifxError::AddrInUse(taken)) => assert_eq!(taken, addr),
+                other => panic!("expected the address to be in use, got {:?}", other),
             }
-            Ok(())
         }
 
-        pub fn worker(
-            recv_sock: UdpSocket,
-            source: u32,
-            receiver_bulbs: Arc<Mutex<HashMap<u64, BulbInfo>>>,
-        ) {
+        #[test]
+        fn test_discovery_storm_batching() {
+            let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+            let recv_sock = UdpSocket::bind(localhost).unwrap();
+            recv_sock
+                .set_read_timeout(Some(Duration::from_secs(1)))
+                .unwrap();
+            let bulb_sock = UdpSocket::bind(localhost).unwrap();
+            for target in 1..=50 {
+                let options = BuildOptions {
+                    target: Some(target),
+                    ..BuildOptions::default()
+                };
+                let msg = Message::StateService {
+                    service: Service::UDP,
+                    port: LIFX_PORT.into(),
+                };
+                let packet = RawMessage::build(&options, msg).unwrap().pack().unwrap();
+                bulb_sock
+                    .send_to(&packet, recv_sock.local_addr().unwrap())
+                    .unwrap();
+            }
+
+            let bulbs = Mutex::new(HashMap::new());
+            let shared = SharedState::new(1);
+            let report = |e: WorkerError| panic!("{}", e);
             let mut buf = [0; 1024];
-            loop {
-                match recv_sock.recv_from(&mut buf) {
-                    Ok((0, addr)) => println!("Received a zero-byte datagram from {:?}", addr),
-                    Ok((nbytes, addr)) => match RawMessage::unpack(&buf[0..nbytes]) {
-                        Ok(raw) => {
-                            if raw.frame_addr.target == 0 {
-                                continue;
-                            }
-                            if let Ok(mut bulbs) = receiver_bulbs.lock() {
-                                let bulb = bulbs
-                                    .entry(raw.frame_addr.target)
-                                    .and_modify(|bulb| bulb.update(addr))
-                                    .or_insert_with(|| {
-                                        BulbInfo::new(source, raw.frame_addr.target, addr)
-                                    });
-                                if let Err(e) = Self::handle_message(raw, bulb) {
-                                    println!("Error handling message from {}: {}", addr, e)
-                                }
-                            }
-                        }
-                        Err(e) => println!("Error unpacking raw message from {}: {}", addr, e),
+            let mut batch = Vec::new();
+            let mut lock_cycles = 0;
+            while bulbs.lock().unwrap().len() < 50 {
+                assert!(Manager::receive_batch(
+                    &recv_sock, &mut buf, &mut batch, &shared, &report
+                ));
+                assert!(!batch.is_empty(), "timed out waiting for datagrams");
+                Manager::apply_batch(&bulbs, &shared, &mut batch, &report);
+                lock_cycles += 1;
+            }
+            // one lock per WORKER_BATCH_SIZE datagrams, rather than one per datagram
+            let expected = if cfg!(unix) {
+                50usize.div_ceil(WORKER_BATCH_SIZE)
+            } else {
+                50
+            };
+            assert_eq!(lock_cycles, expected);
+        }
+
+        /// Makes `limiter` behave as though `elapsed` had passed since it last refilled.
+        fn age_limiter(limiter: &RateLimiter, elapsed: Duration) {
+            limiter.bucket.lock().unwrap().1 -= elapsed;
+        }
+
+        #[test]
+        fn test_rate_limiter() {
+            let rate = Arc::new(AtomicU32::new(5));
+            let limiter = RateLimiter::new(rate.clone());
+
+            // a full bucket allows a burst of `rate` messages, and no more
+            for _ in 0..5 {
+                limiter.try_acquire().unwrap();
+            }
+            assert!(matches!(limiter.try_acquire(), Err(LifxError::RateLimited)));
+
+            // tokens come back at `rate` per second
+            age_limiter(&limiter, Duration::from_millis(450));
+            for _ in 0..2 {
+                limiter.try_acquire().unwrap();
+            }
+            assert!(matches!(limiter.try_acquire(), Err(LifxError::RateLimited)));
+
+            // but the bucket never holds more than a second's worth
+            age_limiter(&limiter, Duration::from_secs(10));
+            for _ in 0..5 {
+                limiter.try_acquire().unwrap();
+            }
+            assert!(matches!(limiter.try_acquire(), Err(LifxError::RateLimited)));
+
+            // and a rate of zero turns limiting off
+            rate.store(0, Ordering::Relaxed);
+            for _ in 0..100 {
+                limiter.try_acquire().unwrap();
+            }
+        }
+
+        #[test]
+        fn test_coalescing() {
+            let shared = SharedState::new(1);
+            shared.rate_limit.store(1, Ordering::Relaxed);
+            shared.coalesce.store(true, Ordering::Relaxed);
+            let bulb = test_bulb_with(&shared);
+            let mock = MockTransport::new();
+            let red = HsbkBuilder::new().saturation_percent(100.0).build();
+            let color = |color| Message::LightSetColor {
+                reserved: 0,
+                color,
+                duration: 0,
+            };
+
+            // the first command uses up the only token, so the rest are queued, and the second
+            // color replaces the first
+            bulb.set_power(&mock, PowerLevel::Enabled).unwrap();
+            bulb.set_bulb_color(&mock, BLACK, 0).unwrap();
+            bulb.set_bulb_color(&mock, red, 0).unwrap();
+            assert_eq!(mock.messages().unwrap().len(), 1);
+
+            // a new token goes to the queued color, rather than letting a newer command of
+            // another kind overtake it
+            age_limiter(&bulb.limiter, Duration::from_secs(1));
+            bulb.set_power(&mock, PowerLevel::Standby).unwrap();
+            assert_eq!(mock.messages().unwrap()[1..], [color(red)]);
+
+            // the last command of each kind is always delivered, in the order they were given
+            while bulb.flush_coalesced(&mock).unwrap() {
+                age_limiter(&bulb.limiter, Duration::from_secs(1));
+            }
+            assert_eq!(
+                mock.messages().unwrap(),
+                [
+                    Message::SetPower {
+                        level: PowerLevel::Enabled
                     },
-                    Err(e) => panic!("recv_from err {:?}", e),
-                }
+                    color(red),
+                    Message::SetPower {
+                        level: PowerLevel::Standby
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        fn test_coalesce_flusher_lifetime() {
+            let mgr = Manager::with_config(ManagerConfig {
+                bind_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+                ..ManagerConfig::default()
+            })
+            .unwrap();
+            // nothing runs until coalescing is turned on
+            assert!(!mgr.flushing.load(Ordering::SeqCst));
+            mgr.enable_coalescing(true).unwrap();
+            assert!(mgr.flushing.load(Ordering::SeqCst));
+
+            // and the thread exits once it's turned off again
+            mgr.enable_coalescing(false).unwrap();
+            let start = Instant::now();
+            while mgr.flushing.load(Ordering::SeqCst) {
+                assert!(
+                    start.elapsed() < Duration::from_secs(1),
+                    "flush thread didn't exit"
+                );
+                sleep(COALESCE_FLUSH_INTERVAL);
             }
+            mgr.enable_coalescing(true).unwrap();
+            assert!(mgr.flushing.load(Ordering::SeqCst));
         }
 
-        pub fn discover(&mut self) -> Result<(), failure::Error> {
-            println!("Doing discovery");
+        #[test]
+        fn test_retransmit() {
+            let mut bulbs = HashMap::from([(1, test_bulb())]);
+            let mock = MockTransport::new();
+            let bulb = &bulbs[&1];
+            bulb.set_power(&mock, PowerLevel::Enabled).unwrap();
+            bulb.set_bulb_color(&mock, BLACK, 0).unwrap();
+            let color_seq = bulb.current_sequence();
+            assert_eq!(bulb.unacknowledged(), 2);
 
-            let opts = BuildOptions {
-                source: self.source,
-                ..Default::default()
+            // nothing is resent before the timeout
+            let patient = RetryPolicy {
+                retries: 2,
+                timeout: Duration::from_secs(60),
             };
-            let rawmsg = RawMessage::build(&opts, Message::GetService).unwrap();
-            let bytes = rawmsg.pack().unwrap();
+            let report = |e: WorkerError| panic!("{}", e);
+            retransmit_all(&bulbs, &mock, &patient, report);
+            assert_eq!(mock.sent().len(), 2);
 
-            for addr in get_if_addrs().unwrap() {
-                if let IfAddr::V4(Ifv4Addr {
-                    broadcast: Some(bcast),
-                    ..
-                }) = addr.addr
-                {
-                    if addr.ip().is_loopback() {
-                        continue;
-                    }
-                    let addr = SocketAddr::new(IpAddr::V4(bcast), 56700);
-                    println!("Discovering bulbs on LAN {:?}", addr);
-                    self.sock.send_to(&bytes, &addr)?;
-                }
+            // an acknowledged message isn't resent, even after the timeout
+            deliver_ack(bulbs.get_mut(&1).unwrap(), color_seq);
+            assert_eq!(bulbs[&1].unacknowledged(), 1);
+            let policy = RetryPolicy {
+                retries: 2,
+                timeout: Duration::ZERO,
+            };
+            let errors = RefCell::new(Vec::new());
+            let report = |e: WorkerError| errors.borrow_mut().push(e);
+            for _ in 0..policy.retries {
+                retransmit_all(&bulbs, &mock, &policy, report);
             }
+            let sent = mock.sent();
+            assert_eq!(sent.len(), 4);
+            // the retries are the same packet, sequence number and all
+            assert_eq!(sent[2].0, sent[0].0);
+            assert_eq!(sent[3].0, sent[0].0);
+            assert!(errors.borrow().is_empty());
 
-            self.last_discovery = Instant::now();
+            // once the retries are used up, the message is given up on and reported
+            retransmit_all(&bulbs, &mock, &policy, report);
+            assert_eq!(mock.sent().len(), 4);
+            assert_eq!(bulbs[&1].unacknowledged(), 0);
+            let errors = errors.into_inner();
+            assert!(
+                matches!(errors[..], [WorkerError::NoAck { target: 1, seq: 1 }]),
+                "{:?}",
+                errors
+            );
+        }
 
-            Ok(())
+        #[test]
+        fn test_sequence_wraps() {
+            let bulb = test_bulb();
+            assert_eq!(bulb.current_sequence(), 0);
+            assert_eq!(bulb.next_sequence(), 1);
+            assert_eq!(bulb.next_sequence(), 2);
+
+            // 0 is skipped on the way round, so it only ever means nothing has been sent
+            bulb.sequence.store(254, Ordering::Relaxed);
+            assert_eq!(bulb.next_sequence(), 255);
+            assert_eq!(bulb.next_sequence(), 1);
+            assert_eq!(bulb.current_sequence(), 1);
+
+            // and the packets sent carry the same numbers
+            let mock = MockTransport::new();
+            bulb.sequence.store(255, Ordering::Relaxed);
+            bulb.set_power(&mock, PowerLevel::Enabled).unwrap();
+            let (packet, _) = &mock.sent()[0];
+            assert_eq!(RawMessage::unpack(packet).unwrap().frame_addr.sequence, 1);
+        }
+
+        #[test]
+        fn test_subscribers() {
+            let mut bulbs = HashMap::new();
+            let shared = SharedState::new(1);
+            let first = shared.subscribers.subscribe();
+            let second = shared.subscribers.subscribe();
+            let mut apply = |msg: Message| {
+                let options = BuildOptions {
+                    target: Some(7),
+                    ..BuildOptions::default()
+                };
+                let raw = RawMessage::build(&options, msg).unwrap();
+                apply_message(&mut bulbs, &shared, raw, test_addr()).unwrap();
+            };
+
+            // every subscriber hears about every event
+            apply(Message::StatePower { level: 65535 });
+            let expected = [
+                BulbEvent::Discovered(7),
+                BulbEvent::PowerChanged {
+                    target: 7,
+                    level: 65535,
+                },
+            ];
+            assert_eq!(first.try_iter().collect::<Vec<_>>(), expected);
+            assert_eq!(second.try_iter().collect::<Vec<_>>(), expected);
+
+            // an unchanged power level isn't an event
+            apply(Message::StatePower { level: 65535 });
+            assert_eq!(first.try_recv(), Err(TryRecvError::Empty));
+
+            // subscribers that hang up are forgotten at the next event
+            drop(second);
+            assert_eq!(shared.subscribers.0.lock().unwrap().len(), 2);
+            apply(Message::StatePower { level: 0 });
+            assert_eq!(shared.subscribers.0.lock().unwrap().len(), 1);
+            let level = 0;
+            let event = BulbEvent::PowerChanged { target: 7, level };
+            assert_eq!(first.try_recv(), Ok(event));
         }
 
-        pub fn refresh(&self) {
-            if let Ok(bulbs) = self.bulbs.lock() {
-                let bulbs = bulbs.values();
-                for bulb in bulbs {
-                    bulb.query_for_missing_info(&self.sock).unwrap();
+        #[test]
+        fn test_short_write() {
+            struct Truncating;
+            impl Transport for Truncating {
+                fn send_to(&self, buf: &[u8], _: SocketAddr) -> io::Result<usize> {
+                    Ok(buf.len() - 1)
                 }
             }
+            let bulb = test_bulb();
+            match bulb.set_power(&Truncating, PowerLevel::Enabled) {
+                Err(LifxError::ShortWrite { sent, expected }) => assert_eq!(sent + 1, expected),
+                other => panic!("expected a short write, got {:?}", other),
+            }
         }
 
-        pub fn add_bulb(&mut self, addr: SocketAddr) -> Result<(), failure::Error> {
-            let opts = BuildOptions {
-                source: self.source,
-                ..Default::default()
-            };
-            let rawmsg = RawMessage::build(&opts, Message::GetService).unwrap();
-            let bytes = rawmsg.pack().unwrap();
-            println!("Attempting connection to: {:?}", addr);
-            self.sock.send_to(&bytes, &addr)?;
-            Ok(())
+        #[test]
+        fn test_set_bulb_color() {
+            let addr = test_addr();
+            let bulb = BulbInfo::new(&SharedState::new(0x1234), 0xd073d5, addr);
+            let mock = MockTransport::new();
+            let red = HsbkBuilder::new().saturation_percent(100.0).build();
+            bulb.set_bulb_color(&mock, red, 500).unwrap();
+
+            let sent = mock.sent();
+            assert_eq!(sent.len(), 1);
+            let (packet, to) = &sent[0];
+            assert_eq!(*to, addr);
+            let raw = RawMessage::unpack(packet).unwrap();
+            assert_eq!(raw.frame.source, 0x1234);
+            assert_eq!(raw.frame_addr.target, 0xd073d5);
+            assert!(raw.frame_addr.ack_required);
+            assert_eq!(
+                Message::from_raw(&raw).unwrap(),
+                Message::LightSetColor {
+                    reserved: 0,
+                    color: red,
+                    duration: 500,
+                }
+            );
         }
 
+        #[test]
+        fn test_zones_slice_chunks() {
+            let mut bulb = test_bulb();
+            set_product(&mut bulb, 38);
+            let zones = Message::StateExtendedColorZones {
+                zones_count: 100,
+                zone_index: 0,
+                colors_count: 0,
+                colors: Box::new([BLACK; EXTENDED_ZONES_PER_MESSAGE]),
+            };
+            deliver(&mut bulb, zones);
+
+            let red = HsbkBuilder::new().saturation_percent(100.0).build();
+            let mock = MockTransport::new();
+            bulb.set_zones_slice(&mock, &[red], 0).unwrap();
+
+            let mut sent = Vec::new();
+            let mut sequences = Vec::new();
+            for (packet, _) in mock.sent() {
+                let raw = RawMessage::unpack(&packet).unwrap();
+                sequences.push(raw.frame_addr.sequence);
+                match Message::from_raw(&raw).unwrap() {
+                    Message::SetExtendedColorZones {
+                        apply,
+                        zone_index,
+                        colors_count,
+                        colors,
+                        ..
+                    } => {
+                        assert!(colors[..colors_count as usize].iter().all(|c| *c == red));
+                        sent.push((zone_index, colors_count, apply));
+                    }
+                    msg => panic!("unexpected message {:?}", msg),
+                }
+            }
+            assert_eq!(
+                sent,
+                [
+                    (0, 82, ApplicationRequest::NoApply),
+                    (82, 18, ApplicationRequest::Apply)
+                ]
+            );
+
+            // the next frame waits until both halves of this one are acknowledged
+            let timeout = Duration::from_secs(60);
+            assert!(!bulb.zone_frame_ready(timeout));
+            assert_eq!(bulb.last_zone_ack(), None);
+            for seq in sequences {
+                deliver_ack(&mut bulb, seq);
+            }
+            assert!(bulb.zone_frame_ready(timeout));
+            assert!(bulb.last_zone_ack().is_some());
+        }
     }
 }
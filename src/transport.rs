@@ -0,0 +1,78 @@
+use lifx_core::{Message, RawMessage};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Mutex, PoisonError};
+
+/// Somewhere [`BulbInfo`](crate::bulb_manager::BulbInfo) can send packets.
+///
+/// This is implemented for [`UdpSocket`], which is what the
+/// [`Manager`](crate::bulb_manager::Manager) uses.  [`MockTransport`] records packets instead, so
+/// commands can be tested without a bulb.
+pub trait Transport {
+    /// Sends `buf` to `addr`, returning how many bytes were sent.
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+}
+
+impl Transport for UdpSocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, addr)
+    }
+}
+
+/// A [`Transport`] that records every packet sent through it, rather than sending it.
+///
+/// ```
+/// use lifx::bulb_manager::Message;
+/// use lifx::transport::{MockTransport, Transport};
+/// use lifx_core::{BuildOptions, RawMessage};
+///
+/// let mock = MockTransport::new();
+/// let raw = RawMessage::build(&BuildOptions::default(), Message::GetPower).unwrap();
+/// mock.send_to(&raw.pack().unwrap(), "10.0.0.2:56700".parse().unwrap())
+///     .unwrap();
+/// assert_eq!(mock.messages().unwrap(), [Message::GetPower]);
+/// ```
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    sent: Mutex<Vec<(Vec<u8>, SocketAddr)>>,
+}
+
+impl MockTransport {
+    pub fn new() -> MockTransport {
+        MockTransport::default()
+    }
+
+    /// Every packet sent so far, oldest first, with the address it was sent to.
+    pub fn sent(&self) -> Vec<(Vec<u8>, SocketAddr)> {
+        self.sent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Decodes every packet sent so far, oldest first.
+    pub fn messages(&self) -> Result<Vec<Message>, lifx_core::Error> {
+        let sent = self.sent.lock().unwrap_or_else(PoisonError::into_inner);
+        sent.iter()
+            .map(|(packet, _)| Message::from_raw(&RawMessage::unpack(packet)?))
+            .collect()
+    }
+
+    /// Forgets every packet sent so far.
+    pub fn clear(&self) {
+        self.sent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+impl Transport for MockTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.sent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((buf.to_vec(), addr));
+        Ok(buf.len())
+    }
+}
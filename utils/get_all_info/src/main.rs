@@ -367,7 +367,7 @@ impl Manager {
                 }
                 let addr = SocketAddr::new(IpAddr::V4(bcast), 56700);
                 println!("Discovering bulbs on LAN {:?}", addr);
-                self.sock.send_to(&bytes, &addr)?;
+                self.sock.send_to(&bytes, addr)?;
             }
         }
 
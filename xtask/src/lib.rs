@@ -61,7 +61,7 @@ impl From<Option<&[u16]>> for TemperatureRange {
 }
 
 impl TemperatureRange {
-    fn fmt(&self) -> Cow<str> {
+    fn fmt(&self) -> Cow<'_, str> {
         match self {
             TemperatureRange::Variable { min, max } => Cow::from(format!(
                 "TemperatureRange::Variable {{ min: {}, max: {} }} ",